@@ -0,0 +1,26 @@
+use hornbill_apilib::*;
+
+#[tokio::main]
+async fn main() {
+    //We get the url of our instance using the async version of get_url_from_name.
+    let url = get_url_from_name_async("demo")
+        .await
+        .expect("We did not get a url for our instance");
+
+    //AsyncXmlmc has the same methods as Xmlmc but invoke needs to be awaited.
+    let mut c = AsyncXmlmc::new(&url).expect("Could not create client");
+
+    // This requires one input paramets of stage which is an unsignedint
+    c.set_param("stage", "1").expect("Could not set stage");
+
+    //We now await the call and check the response.
+    let res = match c.invoke("system", "pingCheck").await {
+        Ok(s) => s,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    println!("{}", &res);
+}
@@ -1,9 +1,32 @@
 use hornbill_apilib::*;
+use std::thread;
 
 fn main() {
-    let url = get_url_from_name("demo").expect("We did not get a url for our instance");
+    //We only need one client. Cloning it is cheap and every clone shares the same connection pool and session.
+    let c = Xmlmc::for_instance("demo").expect("Could not create client");
 
-    let mut _c = Xmlmc::new(&url).expect("Could not create client");
+    //We start a few threads that each build their own Request and send it through their clone of the client.
+    let handles: Vec<_> = (1..=4)
+        .map(|stage| {
+            let c = c.clone();
+            thread::spawn(move || {
+                let mut req = Request::new("system", "pingCheck");
+                req.set_param("stage", &stage.to_string())
+                    .expect("Could not set stage");
 
-    //TODO complete a multithreaded example either with a normal thread pool or tokio.
+                //send only needs &self so nothing is locked while the request is in flight.
+                match c.send(&req) {
+                    Ok(res) => println!("stage {}: {}", stage, res.body()),
+                    Err(e) => println!("stage {}: {}", stage, e),
+                }
+            })
+        })
+        .collect();
+
+    for h in handles {
+        h.join().expect("thread panicked");
+    }
+
+    //The request count is shared between all the clones.
+    println!("requests made: {}", c.get_count());
 }
@@ -1,11 +1,8 @@
 use hornbill_apilib::*;
 
 fn main() {
-    //We get the url of our instance. We should only ever do this once.
-    let url = get_url_from_name("demo").expect("We did not get a url for our instance");
-
-    //We then create our xmlmc object that we can use to query our instance.
-    let mut c = Xmlmc::new(&url).expect("Could not create client");
+    //We create our xmlmc object from the name of our instance. This looks up where the instance is hosted for us.
+    let mut c = Xmlmc::for_instance("demo").expect("Could not create client");
 
     //We need to tell the xmlmc object to copy headers from any response otherwise it will not do this as it can be ineffcient.
     c.set_copy_headers(true);
@@ -19,11 +19,18 @@ pub struct Params {
 }
 
 fn main() {
-    //We get the url of our instance. We should only ever do this once.
-    let url = get_url_from_name("demo").expect("We did not get a url for our instance");
+    //We get the zoneinfo of our instance which tells us where it is hosted. We should only ever do this once.
+    let zone = match ZoneInfoResolver::new().resolve("demo") {
+        Ok(z) => z,
+        Err(e) => {
+            println!("We did not get a url for our instance: {}", e);
+            return;
+        }
+    };
+    println!("demo is on {} ({})", zone.cluster_fqn, zone.release_stream);
 
     //We then create our xmlmc object that we can use to query our instance.
-    let mut c = Xmlmc::new(&url).expect("Could not create client");
+    let mut c = Xmlmc::new(&zone.xmlmc_url()).expect("Could not create client");
 
     //We are going to pick a simple API that does not actully require a login https://api.hornbill.com/system/?op=pingCheck
 
@@ -0,0 +1,35 @@
+use hornbill_apilib::*;
+use serde::Deserialize;
+
+//We only describe what is inside <params>, invoke_as removes the methodCallResult wrapper for us.
+#[derive(Debug, Deserialize)]
+pub struct PingCheck {
+    #[serde(rename = "stageName")]
+    pub stage_name: String,
+    #[serde(rename = "nextStage")]
+    pub next_stage: i64,
+    #[serde(rename = "serviceParamsChecksum")]
+    pub service_params_checksum: Option<String>,
+}
+
+fn main() {
+    //We create our xmlmc object from the name of our instance. This looks up where the instance is hosted for us.
+    let mut c = Xmlmc::for_instance("demo").expect("Could not create client");
+
+    //The same struct works for both xml and json responses so we call the api once in each mode.
+    for json in [false, true] {
+        c.set_json_response(json);
+        c.set_param("stage", "1").expect("Could not set stage");
+
+        //If the call fails or the response does not match our struct we get an Err back.
+        let res = match c.invoke_as::<PingCheck>("system", "pingCheck") {
+            Ok(s) => s,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        println!("json: {} {:?}", json, res.params);
+    }
+}
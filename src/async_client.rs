@@ -0,0 +1,406 @@
+use crate::{
+    metrics, response, session, telemetry, zoneinfo, ApiResponse, AsyncXmlmc, Call, ConnectOptions,
+    Credentials, HttpClient, Relogon, Request, ResolveError, Response, SessionInfo, Step,
+    XmlmcError, ZoneInfo, ZoneInfoResolver,
+};
+use serde::de::DeserializeOwned;
+use session::LogonKind;
+use telemetry::Instrument;
+
+impl AsyncXmlmc {
+    /// You can use this to create a client for your instance from its name. This works the same as Xmlmc::for_instance
+    /// but has to be awaited.
+    /// ```ignore
+    /// let mut c = AsyncXmlmc::for_instance("demo").await?;
+    /// ```
+    pub async fn for_instance(name: &str) -> Result<AsyncXmlmc, XmlmcError> {
+        let zone = ZoneInfoResolver::new()
+            .resolve_async(name)
+            .await
+            .map_err(|e| XmlmcError::Resolve { source: e })?;
+        AsyncXmlmc::builder(&zone.xmlmc_url()).build()
+    }
+
+    /// You can use this to make the http call to the server with the xml you have built. This works the same as the blocking
+    /// Xmlmc::invoke but has to be awaited so it can be used from inside a tokio runtime.
+    /// ```ignore
+    /// let res = c.invoke("system", "pingCheck").await;
+    /// ```
+    pub async fn invoke(&mut self, service: &str, method: &str) -> Result<String, XmlmcError> {
+        self.invoke_response(service, method)
+            .await
+            .map(Response::into_body)
+    }
+
+    /// You can use this in place of invoke to get the params of the response deserialized into your own type.
+    /// This works the same as the blocking Xmlmc::invoke_as but has to be awaited.
+    /// ```ignore
+    /// let res = c.invoke_as::<PingCheck>("system", "pingCheck").await?;
+    /// ```
+    pub async fn invoke_as<T: DeserializeOwned>(
+        &mut self,
+        service: &str,
+        method: &str,
+    ) -> Result<ApiResponse<T>, XmlmcError> {
+        let response = self.invoke_response(service, method).await?;
+        response::decode_response(service, method, response, self.jsonresp)
+    }
+
+    async fn invoke_response(
+        &mut self,
+        service: &str,
+        method: &str,
+    ) -> Result<Response, XmlmcError> {
+        self.request.set_target(service, method);
+        let result = self.call(&self.request).await;
+        self.record_invoke(&result);
+        result
+    }
+
+    /// You can use this to send a Request built separately from the client. This works the same as the blocking
+    /// Xmlmc::send but has to be awaited.
+    /// ```ignore
+    /// let res = c.send(&req).await?;
+    /// ```
+    pub async fn send(&self, req: &Request) -> Result<Response, XmlmcError> {
+        self.call(req).await
+    }
+
+    /// You can use this to log on to your instance with session::userLogon. This works the same as the blocking
+    /// Xmlmc::user_logon but has to be awaited.
+    /// ```ignore
+    /// let info = c.user_logon("admin", "password").await?;
+    /// ```
+    pub async fn user_logon(
+        &self,
+        user_id: &str,
+        password: &str,
+    ) -> Result<SessionInfo, XmlmcError> {
+        let req = session::logon_request(&Credentials::new(user_id, password))?;
+        let response = self.send(&req).await?;
+        self.finish_logon(&req, response, LogonKind::User)
+    }
+
+    /// You can use this to log on to your instance as a guest with session::guestLogon. This works the same as the
+    /// blocking Xmlmc::guest_logon but has to be awaited.
+    /// ```ignore
+    /// let info = c.guest_logon("guest1", "Guest User").await?;
+    /// ```
+    pub async fn guest_logon(
+        &self,
+        guest_id: &str,
+        guest_name: &str,
+    ) -> Result<SessionInfo, XmlmcError> {
+        let req = session::guest_logon_request(guest_id, guest_name)?;
+        let response = self.send(&req).await?;
+        self.finish_logon(&req, response, LogonKind::Guest)
+    }
+
+    /// You can use this to log off the current session. This works the same as the blocking Xmlmc::logoff but has to be awaited.
+    /// ```ignore
+    /// c.logoff().await?;
+    /// ```
+    pub async fn logoff(&self) -> Result<(), XmlmcError> {
+        let req = self
+            .owned_logoff()
+            .unwrap_or_else(|| Request::new("session", "userLogoff"));
+        self.send(&req).await?;
+        self.finish_logoff();
+        Ok(())
+    }
+
+    /// You can use this to log off the session only if it was created by user_logon or guest_logon on this client.
+    /// There is no logoff on drop for the async client as we cannot await in drop, so call this before you finish.
+    /// ```ignore
+    /// c.close().await?;
+    /// ```
+    pub async fn close(&self) -> Result<(), XmlmcError> {
+        if let Some(req) = self.owned_logoff() {
+            self.send(&req).await?;
+            self.finish_logoff();
+        }
+        Ok(())
+    }
+
+    /// You can use this to check if the current session is still valid. This works the same as the blocking
+    /// Xmlmc::is_session_valid but has to be awaited.
+    /// ```ignore
+    /// let valid = c.is_session_valid().await?;
+    /// ```
+    pub async fn is_session_valid(&self) -> Result<bool, XmlmcError> {
+        match self.send(&Request::new("session", "getSessionInfo")).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_session_expired() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    //Sends the request inside a tracing span for the call.
+    async fn call(&self, req: &Request) -> Result<Response, XmlmcError> {
+        let (span, mut call) = self.begin_call(req);
+        let result = self
+            .call_with_relogon(req, &mut call)
+            .instrument(span.clone())
+            .await;
+        self.end_call(req, &span, &call, result)
+    }
+
+    //Sends the request and checks the result, logging back on and sending it again if the session has expired.
+    async fn call_with_relogon(
+        &self,
+        req: &Request,
+        call: &mut Call,
+    ) -> Result<Response, XmlmcError> {
+        let result = self.attempt(req, &call.trace, &mut call.stats).await;
+        if !self.wants_relogon(req, &result) {
+            return result;
+        }
+        {
+            let _guard = self.shared.async_logon.lock().await;
+            match self.relogon(call)? {
+                Relogon::Logon(logon) => {
+                    let response = self
+                        .attempt(&logon, &self.trace_for(&logon), &mut call.stats)
+                        .await?;
+                    //The new session is ours so it is logged off by close and logoff on drop.
+                    self.finish_logon(&logon, response, LogonKind::User)?;
+                }
+                Relogon::Resend => {}
+                Relogon::GiveUp => return result,
+            }
+        }
+        call.stats.retries += 1;
+        self.attempt(req, &call.trace, &mut call.stats).await
+    }
+
+    //Sends the request and checks the result, trying again for as long as the retry policy allows.
+    async fn attempt(
+        &self,
+        req: &Request,
+        trace: &str,
+        stats: &mut metrics::CallStats,
+    ) -> Result<Response, XmlmcError> {
+        let mut attempts = 1;
+        loop {
+            telemetry::attempt(attempts);
+            let result = self.execute(req, trace, stats).await;
+            match self.next_step(req, result, &mut attempts, stats) {
+                Step::Done(result) => return result,
+                Step::Retry(delay) => tokio::time::sleep(delay).await,
+            }
+        }
+    }
+
+    //Makes the http call and reads the response without checking the methodCallResult.
+    async fn execute(
+        &self,
+        req: &Request,
+        trace: &str,
+        stats: &mut metrics::CallStats,
+    ) -> Result<Response, XmlmcError> {
+        let (url, headers, body) = self.before_send(req, trace, stats)?;
+        let wait = self.rate_limit_wait();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        let mut result = self
+            .client
+            .post(url)
+            .headers(headers)
+            .timeout(self.timeout)
+            //The body is wiped once reqwest has finished with it.
+            .body(bytes::Bytes::from_owner(body))
+            .send()
+            .await
+            .map_err(|e| self.send_error(req, trace, e))?;
+
+        let status = result.status();
+        let headers = self.record_response(status, result.headers_mut(), stats);
+        if status != http::StatusCode::OK {
+            let body = result.bytes().await.unwrap_or_default();
+            return Err(self.status_error(req, trace, status.as_u16(), headers, &body, stats));
+        }
+        let body = result.text().await;
+        self.body_result(req, trace, status.as_u16(), headers, body, stats)
+    }
+}
+
+impl ZoneInfoResolver {
+    /// You can use this to get the zoneinfo for an instance from inside an async runtime. It works the same as resolve.
+    /// The cache is read and written on tokio's blocking threads so it does not hold up the runtime.
+    /// ```ignore
+    /// let zone = ZoneInfoResolver::new().resolve_async("demo").await?;
+    /// ```
+    pub async fn resolve_async(&self, instance: &str) -> Result<ZoneInfo, ResolveError> {
+        zoneinfo::check_instance(instance)?;
+        let cached = self.read_cache_async(instance).await;
+        if let Some((zone, true)) = &cached {
+            return Ok(zone.clone());
+        }
+        match self.fetch_async(instance).await {
+            Ok(zone) => {
+                self.write_cache_async(&zone).await;
+                Ok(zone)
+            }
+            Err(e) => cached.map(|(zone, _)| zone).ok_or(e),
+        }
+    }
+
+    /// You can use this to get the zoneinfo from the zoneinfo servers even if it is in the cache from inside an async runtime.
+    /// It works the same as refresh.
+    /// ```ignore
+    /// let zone = resolver.refresh_async("demo").await?;
+    /// ```
+    pub async fn refresh_async(&self, instance: &str) -> Result<ZoneInfo, ResolveError> {
+        zoneinfo::check_instance(instance)?;
+        let zone = self.fetch_async(instance).await?;
+        self.write_cache_async(&zone).await;
+        Ok(zone)
+    }
+
+    //The cache uses std::fs so it is moved onto a blocking thread.
+    async fn read_cache_async(&self, instance: &str) -> Option<(ZoneInfo, bool)> {
+        self.cache_dir.as_ref()?;
+        let resolver = self.clone();
+        let instance = instance.to_owned();
+        tokio::task::spawn_blocking(move || resolver.read_cache(&instance))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn write_cache_async(&self, zone: &ZoneInfo) {
+        if self.cache_dir.is_none() {
+            return;
+        }
+        let resolver = self.clone();
+        let zone = zone.clone();
+        let _ = tokio::task::spawn_blocking(move || resolver.write_cache(&zone)).await;
+    }
+
+    async fn fetch_async(&self, instance: &str) -> Result<ZoneInfo, ResolveError> {
+        let client = reqwest::Client::build(&(&self.options).into())
+            .map_err(|e| ResolveError::Build { source: e })?;
+
+        let mut errors = Vec::new();
+        for url in self.urls(instance) {
+            let result = client
+                .get(&url)
+                .timeout(self.timeout)
+                .header(http::header::USER_AGENT, "reqwest-http/1.1")
+                .send()
+                .await;
+            let response = match result {
+                Ok(r) => r,
+                Err(e) => {
+                    errors.push(ResolveError::Transport { url, source: e });
+                    continue;
+                }
+            };
+            let status = response.status().as_u16();
+            match response.text().await {
+                Ok(body) => match zoneinfo::parse(instance, &url, status, &body) {
+                    Ok(zone) => return Ok(zone),
+                    Err(e) => errors.push(e),
+                },
+                Err(e) => errors.push(ResolveError::Transport { url, source: e }),
+            }
+        }
+        Err(ResolveError::AllMirrorsFailed {
+            instance: instance.to_owned(),
+            errors,
+        })
+    }
+}
+
+/// You can use this to get the https endpoint for your instance from inside an async runtime. It works the same as get_url_from_name.
+/// ```ignore
+/// let url = get_url_from_name_async("demo").await;
+/// ```
+pub async fn get_url_from_name_async(key: &str) -> Option<String> {
+    get_url_from_name_async_with(key, &ConnectOptions::default()).await
+}
+
+/// You can use this to get the https endpoint for your instance from inside an async runtime through a proxy or with extra
+/// certificates. It works the same as get_url_from_name_with.
+/// ```ignore
+/// let url = get_url_from_name_async_with("demo", &opts).await;
+/// ```
+pub async fn get_url_from_name_async_with(key: &str, options: &ConnectOptions) -> Option<String> {
+    ZoneInfoResolver::new()
+        .connect_options(options.clone())
+        .resolve_async(key)
+        .await
+        .ok()
+        .map(|zone| zone.xmlmc_url())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server;
+    use crate::{AsyncXmlmc, Credentials, XmlmcError, ZoneInfoResolver};
+
+    #[tokio::test]
+    async fn test_async_invoke() {
+        let server = test_server::serve(vec![
+            test_server::response(
+                200,
+                &[("Set-Cookie", "ESPSessionState=abc; Path=/")],
+                "<methodCallResult status=\"ok\"><params><stageName>one</stageName></params></methodCallResult>",
+            ),
+            test_server::response(500, &[], "oops"),
+        ]);
+        let mut c = AsyncXmlmc::new(&server.url).unwrap();
+
+        c.set_param("stage", "1").unwrap();
+        let res = c.invoke("system", "pingCheck").await.unwrap();
+        assert!(res.contains("<stageName>one</stageName>"));
+        assert_eq!(c.get_session_id(), "ESPSessionState=abc");
+        assert!(server
+            .requests
+            .recv()
+            .unwrap()
+            .contains("<params><stage>1</stage>\n</params>"));
+
+        let err = c.invoke("system", "pingCheck").await.unwrap_err();
+        assert!(matches!(&err, XmlmcError::Status(e) if e.status == 500));
+        assert_eq!(c.get_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_async_relogon() {
+        let ok = "<methodCallResult status=\"ok\"></methodCallResult>";
+        let server = test_server::serve(vec![
+            test_server::response(401, &[], "session expired"),
+            test_server::response(200, &[("Set-Cookie", "ESPSessionState=new; Path=/")], ok),
+            test_server::response(200, &[], ok),
+        ]);
+        let mut c = AsyncXmlmc::new(&server.url).unwrap();
+        c.set_sessionid("ESPSessionState=old");
+        c.set_credentials(Credentials::new("admin", "password"));
+
+        assert_eq!(c.invoke("system", "pingCheck").await.unwrap(), ok);
+        assert_eq!(c.get_session_id(), "ESPSessionState=new");
+
+        let requests: Vec<String> = server.requests.iter().collect();
+        assert!(requests[1].contains("session/?method=userLogon"));
+        assert!(requests[2].contains("ESPSessionState=new"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_async_cache() {
+        let zoneinfo = r#"{"zoneinfo":{"clusterFqn":"mdh-p01","releaseStream":"stable","endpoint":"https://mdh-p01-api.hornbill.com/demo/","message":"Success"}}"#;
+        let server = test_server::serve(vec![test_server::response(200, &[], zoneinfo)]);
+        let mirror = server.url.trim_end_matches("/demo/xmlmc").to_owned();
+        let dir = tempfile::tempdir().unwrap();
+
+        let resolver = ZoneInfoResolver::new()
+            .mirrors(&[&mirror])
+            .cache_dir(dir.path());
+        let zone = resolver.resolve_async("demo").await.unwrap();
+        assert!(dir.path().join("demo.json").exists());
+        //The server only answers once so this has to come from the cache.
+        assert_eq!(resolver.resolve_async("demo").await.unwrap(), zone);
+    }
+}
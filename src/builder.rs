@@ -0,0 +1,194 @@
+use crate::transport::ClientConfig;
+use crate::{ConnectOptions, HttpClient, Request, RetryPolicy, Shared, XmlmcClient, XmlmcError};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// You can use this to set up a client with everything configured before it is created. Get one from Xmlmc::builder
+/// or AsyncXmlmc::builder.
+/// ```ignore
+/// let c = Xmlmc::builder(&url)
+///     .timeout(Duration::from_secs(60))
+///     .user_agent("demo_ldapimport/1.1")
+///     .api_key("1234567890")
+///     .build()?;
+/// ```
+pub struct XmlmcBuilder<C> {
+    url: String,
+    timeout: Duration,
+    user_agent: String,
+    api_key: Zeroizing<String>,
+    json: bool,
+    trace_prefix: String,
+    auto_trace: bool,
+    config: ClientConfig,
+    client: PhantomData<C>,
+}
+
+impl<C: HttpClient> XmlmcClient<C> {
+    /// You can use this to get a builder for a client that sends requests to url.
+    /// ```ignore
+    /// let c = Xmlmc::builder(&url).build()?;
+    /// ```
+    pub fn builder(url: &str) -> XmlmcBuilder<C> {
+        XmlmcBuilder {
+            url: url.to_owned(),
+            timeout: Duration::from_secs(30),
+            user_agent: "rust_apilib/1.1".to_owned(),
+            api_key: Zeroizing::default(),
+            json: false,
+            trace_prefix: "rust_apilib".to_owned(),
+            auto_trace: false,
+            config: ClientConfig::default(),
+            client: PhantomData,
+        }
+    }
+}
+
+impl<C: HttpClient> XmlmcBuilder<C> {
+    /// You can use this to set how long a request can take before it fails with XmlmcError::Timeout. This defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> XmlmcBuilder<C> {
+        self.timeout = timeout;
+        self
+    }
+
+    /// You can use this to set how long we wait for the connection to the server to open.
+    pub fn connect_timeout(mut self, timeout: Duration) -> XmlmcBuilder<C> {
+        self.config.connect = self.config.connect.connect_timeout(timeout);
+        self
+    }
+
+    /// You can use this to set the useragent string that is sent to the hornbill server. This defaults to "rust_apilib/1.1"
+    pub fn user_agent(mut self, user_agent: &str) -> XmlmcBuilder<C> {
+        self.user_agent = user_agent.to_owned();
+        self
+    }
+
+    /// You can use this to send all requests through a proxy.
+    /// ```ignore
+    /// let c = Xmlmc::builder(&url).proxy("http://proxy.local:3128").build()?;
+    /// ```
+    pub fn proxy(mut self, url: &str) -> XmlmcBuilder<C> {
+        self.config.connect = self.config.connect.proxy(url);
+        self
+    }
+
+    /// You can use this to trust another root certificate, or a bundle of them, in PEM format as well as the built in ones.
+    /// ```ignore
+    /// let c = Xmlmc::builder(&url).add_root_certificate_pem(&std::fs::read("ca.pem")?).build()?;
+    /// ```
+    pub fn add_root_certificate_pem(mut self, pem: &[u8]) -> XmlmcBuilder<C> {
+        self.config.connect = self.config.connect.add_root_certificate_pem(pem);
+        self
+    }
+
+    /// You can use this to set all of the proxy and TLS options at once, for example to share them with get_url_from_name_with.
+    /// This replaces anything set with connect_timeout, proxy or add_root_certificate_pem.
+    /// ```ignore
+    /// let opts = ConnectOptions::new().proxy("http://proxy.corp.local:8080").identity_pem(&pem);
+    /// let url = get_url_from_name_with("demo", &opts).expect("no url");
+    /// let c = Xmlmc::builder(&url).connect_options(opts).build()?;
+    /// ```
+    pub fn connect_options(mut self, options: ConnectOptions) -> XmlmcBuilder<C> {
+        self.config.connect = options;
+        self
+    }
+
+    /// You can use this to set headers that are sent with every request. The headers the library sets itself, like
+    /// Content-Type and Cookie, take priority.
+    pub fn default_headers(mut self, headers: http::header::HeaderMap) -> XmlmcBuilder<C> {
+        self.config.default_headers = headers;
+        self
+    }
+
+    /// You can use this to set the APIkey used to identify yourself. See Xmlmc::set_apikey.
+    pub fn api_key(mut self, key: &str) -> XmlmcBuilder<C> {
+        self.api_key = Zeroizing::new(key.to_owned());
+        self
+    }
+
+    /// You can use this to ask for json responses from the server. See Xmlmc::set_json_response.
+    pub fn json_response(mut self, b: bool) -> XmlmcBuilder<C> {
+        self.json = b;
+        self
+    }
+
+    /// You can use this to set what goes before the trace identifier in the trace sent to the server. See Xmlmc::set_trace_prefix.
+    pub fn trace_prefix(mut self, prefix: &str) -> XmlmcBuilder<C> {
+        self.trace_prefix = prefix.to_owned();
+        self
+    }
+
+    /// You can use this to make up a trace identifier for every call that does not have one. See Xmlmc::set_auto_trace.
+    pub fn auto_trace(mut self, b: bool) -> XmlmcBuilder<C> {
+        self.auto_trace = b;
+        self
+    }
+
+    /// You can use this to set how long an idle connection is kept in the pool.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> XmlmcBuilder<C> {
+        self.config.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// You can use this to set how many idle connections are kept in the pool.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> XmlmcBuilder<C> {
+        self.config.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// You can use this to create the client. This fails with XmlmcError::InvalidUrl if the url is not a http or https url
+    /// and XmlmcError::Build if the proxy, certificates or identity are not valid or the http client could not be created.
+    pub fn build(self) -> Result<XmlmcClient<C>, XmlmcError> {
+        let server = normalise_url(&self.url)?;
+        let client = C::build(&self.config).map_err(|e| XmlmcError::Build { source: e })?;
+
+        Ok(XmlmcClient {
+            server,
+            request: Request::default(),
+            statuscode: 0,
+            timeout: self.timeout,
+            api_key: self.api_key,
+            trace: "".to_owned(),
+            trace_prefix: self.trace_prefix,
+            auto_trace: self.auto_trace,
+            last_trace: String::new(),
+            jsonresp: self.json,
+            user_agent: self.user_agent,
+            copy_headers: false,
+            headers: http::header::HeaderMap::new(),
+            credentials: None,
+            retry: RetryPolicy::default(),
+            shared: Arc::new(Shared::default()),
+            client,
+        })
+    }
+}
+
+//Checks the url is something we can post to and tidies the slashes in the path so it always ends in exactly one,
+//whether or not the caller added one.
+fn normalise_url(url: &str) -> Result<String, XmlmcError> {
+    let invalid = |reason: &str| XmlmcError::InvalidUrl {
+        url: url.to_owned(),
+        reason: reason.to_owned(),
+    };
+    let mut parsed = reqwest::Url::parse(url.trim()).map_err(|e| invalid(&e.to_string()))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(invalid("the url must be http or https"));
+    }
+    if parsed.host_str().is_none_or(str::is_empty) {
+        return Err(invalid("the url must have a host"));
+    }
+    if parsed.query().is_some() || parsed.fragment().is_some() {
+        return Err(invalid("the url cannot have a query or fragment"));
+    }
+    let path: Vec<&str> = parsed.path().split('/').filter(|p| !p.is_empty()).collect();
+    let path = if path.is_empty() {
+        "/".to_owned()
+    } else {
+        format!("/{}/", path.join("/"))
+    };
+    parsed.set_path(&path);
+    Ok(parsed.to_string())
+}
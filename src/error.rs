@@ -0,0 +1,383 @@
+use std::fmt;
+use std::time::Duration;
+
+/// The error type returned by the xmlmc client.
+/// Every variant that relates to an api call carries the service and method that was being invoked so you can tell which call failed.
+/// The message only says what went wrong at this level. The cause, like the connection error for a Transport or the last
+/// error for RetriesExhausted, is returned by source so you can walk down to it.
+/// ```ignore
+/// match c.invoke("system", "pingCheck") {
+///     Ok(s) => println!("{}", s),
+///     Err(XmlmcError::Status(e)) => println!("server returned {}", e.status),
+///     Err(e) => {
+///         let mut cause: Option<&dyn std::error::Error> = Some(&e);
+///         while let Some(c) = cause {
+///             println!("{}", c);
+///             cause = c.source();
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum XmlmcError {
+    /// The request could not be sent to the server, for example the connection was refused or reset.
+    Transport {
+        service: String,
+        method: String,
+        trace: String,
+        source: reqwest::Error,
+    },
+    /// The request did not complete before the client timeout expired.
+    Timeout {
+        service: String,
+        method: String,
+        trace: String,
+        source: reqwest::Error,
+    },
+    /// The server responded with a http status code other than 200.
+    Status(Box<StatusError>),
+    /// The server responded with a 200 but we could not read the response body.
+    Body {
+        service: String,
+        method: String,
+        status: u16,
+        trace: String,
+        source: reqwest::Error,
+    },
+    /// The server processed the call but the methodCallResult reported that it failed.
+    Api(Box<ApiError>),
+    /// The server responded but the response could not be deserialized into the type given to invoke_as.
+    Deserialize(Box<DeserializeError>),
+    /// An element or attribute name given to the param builder is not valid xml.
+    InvalidName { name: String, reason: String },
+    /// close_element was called for an element that is not the last one opened. expected is the element that is
+    /// still open, if there is one.
+    MismatchedElement {
+        expected: Option<String>,
+        found: String,
+    },
+    /// A value passed to set_params_from or to_params could not be turned into params.
+    Serialize(String),
+    /// invoke or send was called while elements were still open. elements lists them outermost first.
+    UnclosedElements {
+        service: String,
+        method: String,
+        elements: Vec<String>,
+    },
+    /// A param or attribute value contains a character that is not allowed in xml and set_control_chars is ControlChars::Error.
+    /// name is the element or attribute and position counts characters from 1.
+    IllegalCharacter {
+        name: String,
+        character: char,
+        position: usize,
+    },
+    /// The xml given to append_raw_xml is not a well formed fragment.
+    InvalidXml { reason: String },
+    /// The url given for the instance is not a http or https url we can send requests to.
+    InvalidUrl { url: String, reason: String },
+    /// The zoneinfo for the instance given to for_instance could not be found.
+    Resolve { source: crate::ResolveError },
+    /// The http client could not be created, for example because the proxy url or a certificate was not valid.
+    Build { source: reqwest::Error },
+    /// The call kept failing with an error the RetryPolicy allows to be retried until every attempt was used.
+    /// source is the error from the last attempt.
+    RetriesExhausted {
+        attempts: u32,
+        source: Box<XmlmcError>,
+    },
+}
+
+/// The details of an XmlmcError::Status. These are boxed so that a Result holding an XmlmcError stays small.
+/// body holds whatever the server sent back and headers are only kept when set_copy_headers(true) has been called.
+/// retry_after is how long the server asked us to wait in its Retry-After header, if it sent one.
+/// ```ignore
+/// if let Err(XmlmcError::Status(e)) = c.invoke("system", "pingCheck") {
+///     println!("{} returned {}: {}", e.method, e.status, e.body);
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct StatusError {
+    pub service: String,
+    pub method: String,
+    pub status: u16,
+    pub trace: String,
+    pub headers: Option<http::header::HeaderMap>,
+    pub body: String,
+    pub retry_after: Option<Duration>,
+}
+
+/// The details of an XmlmcError::Api. code and message are taken from the state element and body holds the full
+/// response. headers are only kept when set_copy_headers(true) has been called.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ApiError {
+    pub service: String,
+    pub method: String,
+    pub status: u16,
+    pub trace: String,
+    pub code: Option<String>,
+    pub message: String,
+    pub headers: Option<http::header::HeaderMap>,
+    pub body: String,
+}
+
+/// The details of an XmlmcError::Deserialize. message says what could not be deserialized and body holds the full response.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct DeserializeError {
+    pub service: String,
+    pub method: String,
+    pub trace: String,
+    pub message: String,
+    pub body: String,
+}
+
+impl XmlmcError {
+    /// The http status code the server returned, if we got as far as receiving a response.
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            XmlmcError::Status(e) => Some(e.status),
+            XmlmcError::Api(e) => Some(e.status),
+            XmlmcError::Body { status, .. } => Some(*status),
+            XmlmcError::Transport { source, .. } | XmlmcError::Timeout { source, .. } => {
+                source.status().map(|s| s.as_u16())
+            }
+            XmlmcError::InvalidName { .. }
+            | XmlmcError::MismatchedElement { .. }
+            | XmlmcError::Serialize(_)
+            | XmlmcError::Deserialize(_)
+            | XmlmcError::UnclosedElements { .. }
+            | XmlmcError::IllegalCharacter { .. }
+            | XmlmcError::InvalidXml { .. }
+            | XmlmcError::InvalidUrl { .. }
+            | XmlmcError::Resolve { .. }
+            | XmlmcError::Build { .. } => None,
+            XmlmcError::RetriesExhausted { source, .. } => source.status_code(),
+        }
+    }
+
+    /// The service of the api call that failed.
+    pub fn service(&self) -> Option<&str> {
+        match self {
+            XmlmcError::Transport { service, .. }
+            | XmlmcError::Timeout { service, .. }
+            | XmlmcError::Body { service, .. }
+            | XmlmcError::UnclosedElements { service, .. } => Some(service),
+            XmlmcError::Status(e) => Some(&e.service),
+            XmlmcError::Api(e) => Some(&e.service),
+            XmlmcError::Deserialize(e) => Some(&e.service),
+            XmlmcError::RetriesExhausted { source, .. } => source.service(),
+            XmlmcError::InvalidName { .. }
+            | XmlmcError::MismatchedElement { .. }
+            | XmlmcError::Serialize(_)
+            | XmlmcError::IllegalCharacter { .. }
+            | XmlmcError::InvalidXml { .. }
+            | XmlmcError::InvalidUrl { .. }
+            | XmlmcError::Resolve { .. }
+            | XmlmcError::Build { .. } => None,
+        }
+    }
+
+    /// The method of the api call that failed.
+    pub fn method(&self) -> Option<&str> {
+        match self {
+            XmlmcError::Transport { method, .. }
+            | XmlmcError::Timeout { method, .. }
+            | XmlmcError::Body { method, .. }
+            | XmlmcError::UnclosedElements { method, .. } => Some(method),
+            XmlmcError::Status(e) => Some(&e.method),
+            XmlmcError::Api(e) => Some(&e.method),
+            XmlmcError::Deserialize(e) => Some(&e.method),
+            XmlmcError::RetriesExhausted { source, .. } => source.method(),
+            XmlmcError::InvalidName { .. }
+            | XmlmcError::MismatchedElement { .. }
+            | XmlmcError::Serialize(_)
+            | XmlmcError::IllegalCharacter { .. }
+            | XmlmcError::InvalidXml { .. }
+            | XmlmcError::InvalidUrl { .. }
+            | XmlmcError::Resolve { .. }
+            | XmlmcError::Build { .. } => None,
+        }
+    }
+
+    /// The trace that was sent with the call that failed, as it will appear in the server logs.
+    pub fn trace(&self) -> Option<&str> {
+        match self {
+            XmlmcError::Transport { trace, .. }
+            | XmlmcError::Timeout { trace, .. }
+            | XmlmcError::Body { trace, .. } => Some(trace),
+            XmlmcError::Status(e) => Some(&e.trace),
+            XmlmcError::Api(e) => Some(&e.trace),
+            XmlmcError::Deserialize(e) => Some(&e.trace),
+            XmlmcError::RetriesExhausted { source, .. } => source.trace(),
+            _ => None,
+        }
+    }
+
+    /// The response body the server sent back, if any.
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            XmlmcError::Status(e) => Some(&e.body),
+            XmlmcError::Api(e) => Some(&e.body),
+            XmlmcError::Deserialize(e) => Some(&e.body),
+            XmlmcError::RetriesExhausted { source, .. } => source.body(),
+            _ => None,
+        }
+    }
+
+    /// The response headers for a failed call. These are only saved when set_copy_headers(true) has been called.
+    pub fn headers(&self) -> Option<&http::header::HeaderMap> {
+        match self {
+            XmlmcError::Status(e) => e.headers.as_ref(),
+            XmlmcError::Api(e) => e.headers.as_ref(),
+            XmlmcError::RetriesExhausted { source, .. } => source.headers(),
+            _ => None,
+        }
+    }
+
+    /// How long the server asked us to wait before trying again, from the Retry-After header of a 429 or 503 response.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            XmlmcError::Status(e) => e.retry_after,
+            XmlmcError::RetriesExhausted { source, .. } => source.retry_after(),
+            _ => None,
+        }
+    }
+
+    /// The number of attempts that were made at the call if it was given up on after retrying.
+    pub fn attempts(&self) -> Option<u32> {
+        match self {
+            XmlmcError::RetriesExhausted { attempts, .. } => Some(*attempts),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the call failed because the session is not valid any more, either with a 401 status
+    /// or an api error saying the session is invalid or has expired.
+    pub fn is_session_expired(&self) -> bool {
+        match self {
+            XmlmcError::Status(e) => e.status == 401,
+            XmlmcError::Api(e) => {
+                let message = e.message.to_lowercase();
+                message.contains("session")
+                    && (message.contains("expired") || message.contains("invalid"))
+            }
+            XmlmcError::RetriesExhausted { source, .. } => source.is_session_expired(),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn invalid_name(name: &str, reason: &str) -> XmlmcError {
+        XmlmcError::InvalidName {
+            name: name.to_owned(),
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for XmlmcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmlmcError::Transport {
+                service, method, ..
+            } => write!(f, "{}::{} request failed", service, method),
+            XmlmcError::Timeout {
+                service, method, ..
+            } => write!(f, "{}::{} request timed out", service, method),
+            XmlmcError::Status(e) => {
+                match crate::response::check_envelope(&e.body, e.body.trim_start().starts_with('{'))
+                {
+                    Some(failure) if !failure.message.is_empty() => write!(
+                        f,
+                        "{}::{} returned http status {}: {}",
+                        e.service, e.method, e.status, failure.message
+                    ),
+                    _ => write!(
+                        f,
+                        "{}::{} returned http status {}",
+                        e.service, e.method, e.status
+                    ),
+                }
+            }
+            XmlmcError::Body {
+                service, method, ..
+            } => write!(f, "{}::{} response body could not be read", service, method),
+            XmlmcError::Api(e) => write!(f, "{}::{} failed: {}", e.service, e.method, e.message),
+            XmlmcError::Deserialize(e) => write!(
+                f,
+                "{}::{} response could not be deserialized: {}",
+                e.service, e.method, e.message
+            ),
+            XmlmcError::InvalidName { name, reason } => write!(f, "{}: {:?}", reason, name),
+            XmlmcError::MismatchedElement {
+                expected: Some(expected),
+                found,
+            } => write!(
+                f,
+                "Xml element {:?} cannot be closed before {:?}",
+                found, expected
+            ),
+            XmlmcError::MismatchedElement {
+                expected: None,
+                found,
+            } => write!(f, "Xml element {:?} is not open", found),
+            XmlmcError::Serialize(msg) => write!(f, "could not serialize params: {}", msg),
+            XmlmcError::UnclosedElements {
+                service,
+                method,
+                elements,
+            } => write!(
+                f,
+                "{}::{} has unclosed xml elements: {}",
+                service,
+                method,
+                elements.join(", ")
+            ),
+            XmlmcError::IllegalCharacter {
+                name,
+                character,
+                position,
+            } => write!(
+                f,
+                "{:?} contains U+{:04X} at position {} which is not allowed in xml",
+                name, *character as u32, position
+            ),
+            XmlmcError::InvalidXml { reason } => {
+                write!(f, "Xml fragment is not well formed: {}", reason)
+            }
+            XmlmcError::InvalidUrl { url, reason } => {
+                write!(f, "{:?} is not a valid url: {}", url, reason)
+            }
+            XmlmcError::Resolve { .. } => write!(f, "could not find the url for the instance"),
+            XmlmcError::Build { .. } => write!(f, "could not build the http client"),
+            XmlmcError::RetriesExhausted { attempts, source } => {
+                match (source.service(), source.method()) {
+                    (Some(service), Some(method)) => {
+                        write!(
+                            f,
+                            "{}::{} gave up after {} attempts",
+                            service, method, attempts
+                        )
+                    }
+                    _ => write!(f, "gave up after {} attempts", attempts),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for XmlmcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            XmlmcError::Transport { source, .. }
+            | XmlmcError::Timeout { source, .. }
+            | XmlmcError::Body { source, .. }
+            | XmlmcError::Build { source } => Some(source),
+            XmlmcError::Resolve { source } => Some(source),
+            XmlmcError::RetriesExhausted { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+//How many of the most recent call latencies are kept for the percentiles.
+const LATENCY_SAMPLES: usize = 1024;
+
+/// A snapshot of the calls made by a client and all of its clones. Get one with Xmlmc::metrics.
+/// ```ignore
+/// let m = c.metrics();
+/// println!("{} calls, {} failed, p99 {:?}", m.calls, m.failures, m.latency.p99);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metrics {
+    /// The number of calls made. Each invoke or send counts once however many times it was sent.
+    pub calls: u64,
+    /// The number of calls that succeeded.
+    pub successes: u64,
+    /// The number of calls that failed.
+    pub failures: u64,
+    /// The number of times a request was sent again, either by the RetryPolicy or after logging back on.
+    pub retries: u64,
+    /// The calls made to each service and method.
+    pub methods: BTreeMap<(String, String), MethodMetrics>,
+    /// How many responses came back with each http status code, counting every attempt.
+    pub statuses: BTreeMap<u16, u64>,
+    /// The size of the request bodies sent, counting every attempt.
+    pub bytes_sent: u64,
+    /// The size of the response bodies received, counting every attempt.
+    pub bytes_received: u64,
+    /// How long calls took, from the most recent 1024 calls.
+    pub latency: Latency,
+}
+
+/// The calls made to one service and method.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MethodMetrics {
+    pub calls: u64,
+    pub successes: u64,
+    pub failures: u64,
+    pub retries: u64,
+}
+
+/// Percentiles of how long calls took, including any retries and waiting for the rate limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Latency {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// The details of one finished call, passed to a MetricsHook.
+#[derive(Clone, Debug)]
+pub struct CallMetrics<'a> {
+    pub service: &'a str,
+    pub method: &'a str,
+    /// The trace that was sent with the call.
+    pub trace: &'a str,
+    /// The http status code of the last response, if the server sent one.
+    pub status: Option<u16>,
+    pub success: bool,
+    /// The number of times the request was sent again.
+    pub retries: u32,
+    pub latency: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Something that is told about every call as it finishes, so you can export the metrics to Prometheus or similar.
+/// This is implemented for any closure taking a &CallMetrics. It is called on the thread or task that made the call
+/// so it should be quick.
+/// ```ignore
+/// c.set_metrics_hook(move |call: &CallMetrics| {
+///     requests.with_label_values(&[call.service, call.method]).inc();
+///     latency.observe(call.latency.as_secs_f64());
+/// });
+/// ```
+pub trait MetricsHook: Send + Sync {
+    fn on_call(&self, call: &CallMetrics);
+}
+
+impl<F> MetricsHook for F
+where
+    F: Fn(&CallMetrics) + Send + Sync,
+{
+    fn on_call(&self, call: &CallMetrics) {
+        self(call)
+    }
+}
+
+//What happened while making one call, filled in as each attempt is made.
+#[derive(Default)]
+pub(crate) struct CallStats {
+    pub(crate) retries: u32,
+    pub(crate) statuses: Vec<u16>,
+    pub(crate) bytes_sent: u64,
+    pub(crate) bytes_received: u64,
+}
+
+//The running totals shared between all clones of a client.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    totals: Metrics,
+    //The latest latencies, once it is full next is the oldest one which gets replaced.
+    samples: Vec<Duration>,
+    next: usize,
+}
+
+impl Recorder {
+    pub(crate) fn record(&mut self, call: &CallMetrics, stats: &CallStats) {
+        let totals = &mut self.totals;
+        let method = totals
+            .methods
+            .entry((call.service.to_owned(), call.method.to_owned()))
+            .or_default();
+        totals.calls += 1;
+        method.calls += 1;
+        if call.success {
+            totals.successes += 1;
+            method.successes += 1;
+        } else {
+            totals.failures += 1;
+            method.failures += 1;
+        }
+        totals.retries += u64::from(call.retries);
+        method.retries += u64::from(call.retries);
+        for status in &stats.statuses {
+            *totals.statuses.entry(*status).or_default() += 1;
+        }
+        totals.bytes_sent += call.bytes_sent;
+        totals.bytes_received += call.bytes_received;
+
+        if self.samples.len() < LATENCY_SAMPLES {
+            self.samples.push(call.latency);
+        } else {
+            self.samples[self.next] = call.latency;
+        }
+        self.next = (self.next + 1) % LATENCY_SAMPLES;
+    }
+
+    pub(crate) fn snapshot(&self) -> Metrics {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let mut metrics = self.totals.clone();
+        metrics.latency = Latency {
+            p50: percentile(&sorted, 0.5),
+            p90: percentile(&sorted, 0.9),
+            p99: percentile(&sorted, 0.99),
+            max: sorted.last().copied().unwrap_or_default(),
+        };
+        metrics
+    }
+}
+
+//The nearest rank percentile of the sorted latencies.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
@@ -0,0 +1,893 @@
+use crate::{ser, XmlmcError};
+use base64::{engine::general_purpose, Engine as _};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Attributes that can be appended to an xml element.
+pub struct Attributes {
+    pub(crate) key: String,
+    pub(crate) value: String,
+}
+
+impl Attributes {
+    /// You can use this to create an attribute to pass to set_param_attr.
+    /// ```ignore
+    /// let attr = Attributes::new("columnName", "h_firstname");
+    /// ```
+    pub fn new(key: &str, value: &str) -> Attributes {
+        Attributes {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+}
+
+/// A single api call that can be sent with Xmlmc::send. This holds the service, method, params and trace for the call
+/// so you can build requests on different threads and send them all through one shared Xmlmc client.
+/// ```ignore
+/// let mut req = Request::new("system", "pingCheck");
+/// req.set_param("stage", "1")?;
+/// let res = c.send(&req)?;
+/// ```
+#[derive(Clone, Default)]
+pub struct Request {
+    service: String,
+    method: String,
+    paramsxml: String,
+    trace: String,
+    //The elements that have been opened but not closed yet, innermost last.
+    open: Vec<String>,
+    rules: Rules,
+    //Where the values of params added with set_param_sensitive are in paramsxml.
+    sensitive: Vec<(usize, usize)>,
+}
+
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("service", &self.service)
+            .field("method", &self.method)
+            .field("params", &self.get_params_redacted())
+            .field("trace", &self.trace)
+            .field("open", &self.open)
+            .finish()
+    }
+}
+
+//A request holding a password is wiped from memory once it is finished with.
+impl Drop for Request {
+    fn drop(&mut self) {
+        if !self.sensitive.is_empty() {
+            self.paramsxml.zeroize();
+        }
+    }
+}
+
+//The rules a request checks names and values against. These are kept when the params are cleared.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Rules {
+    pub(crate) ascii_names: bool,
+    pub(crate) control_chars: ControlChars,
+}
+
+/// What to do with characters that are not allowed in XML 1.0, such as U+0000 to U+0008 or U+FFFE, when they are found in
+/// a param or attribute value. Tab, carriage return and line feed are allowed and are always kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ControlChars {
+    /// Leave the character out.
+    Strip,
+    /// Replace the character with the unicode replacement character U+FFFD.
+    #[default]
+    Replace,
+    /// Fail with XmlmcError::IllegalCharacter.
+    Error,
+}
+
+impl Request {
+    /// You can use this to create a new request for a service and method.
+    /// ```ignore
+    /// let mut req = Request::new("session", "userLogon");
+    /// ```
+    pub fn new(service: &str, method: &str) -> Request {
+        let mut req = Request::default();
+        req.set_target(service, method);
+        req
+    }
+
+    /// The service this request will call.
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// The method this request will call.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// You can use this to set a trace identifier for this request only. If this is not set the trace set on the client is used.
+    /// ```ignore
+    /// req.set_trace("0987654321zxc");
+    /// ```
+    pub fn set_trace(&mut self, s: &str) {
+        self.trace = s.to_owned();
+    }
+
+    /// The trace identifier set on this request.
+    pub fn trace(&self) -> &str {
+        &self.trace
+    }
+
+    /// You can use this to only allow ASCII letters, digits, '_', '-', '.' and ':' in element and attribute names.
+    /// By default any name that is valid in XML 1.0 is allowed.
+    /// ```ignore
+    /// req.set_ascii_names(true);
+    /// ```
+    pub fn set_ascii_names(&mut self, b: bool) {
+        self.rules.ascii_names = b;
+    }
+
+    pub(crate) fn set_target(&mut self, service: &str, method: &str) {
+        self.service = service.to_owned();
+        self.method = method.to_owned();
+    }
+
+    /// You can use this to choose what happens to characters that are not allowed in xml when they are in a value.
+    /// By default they are replaced with the unicode replacement character.
+    /// ```ignore
+    /// req.set_control_chars(ControlChars::Strip);
+    /// ```
+    pub fn set_control_chars(&mut self, policy: ControlChars) {
+        self.rules.control_chars = policy;
+    }
+
+    /// You can add parameters to the xml you will be sending to the server.
+    /// Any characters that are not allowed in xml are dealt with as set by set_control_chars.
+    /// ```ignore
+    /// req.set_param("username","admin");
+    /// ```
+    pub fn set_param(&mut self, key: &str, value: &str) -> Result<(), XmlmcError> {
+        check_element_name(key, self.rules)?;
+        let cleaned = encode_value(key, value, self.rules)?;
+
+        //We neet to check that the input is valid utf8 otherwise we cannot add it to a rust string.
+        //We are going to
+        let clean_value = String::from_utf8_lossy(cleaned.as_bytes());
+
+        self.push_params(&["<", key, ">", &clean_value, "</", key, ">"]);
+        Ok(())
+    }
+
+    /// You can use this to add a param that holds a secret, like a password. It is sent as it is but get_params_redacted
+    /// and the Debug output hide its value. The params are wiped from memory when the request is dropped or cleared, and
+    /// so is any copy left behind as more params are added after it.
+    /// ```ignore
+    /// req.set_param_sensitive("password", &encoded_password);
+    /// ```
+    pub fn set_param_sensitive(&mut self, key: &str, value: &str) -> Result<(), XmlmcError> {
+        check_element_name(key, self.rules)?;
+        let mut cleaned = encode_value(key, value, self.rules)?;
+        self.push_params(&["<", key, ">"]);
+        let start = self.paramsxml.len();
+        self.push_params(&[&cleaned]);
+        self.sensitive.push((start, self.paramsxml.len()));
+        self.push_params(&["</", key, ">"]);
+        cleaned.zeroize();
+        Ok(())
+    }
+
+    /// You can use this to add binary data, like a file attachment, as a base64 encoded param.
+    /// ```ignore
+    /// req.set_param_bytes("content", &std::fs::read("report.pdf")?);
+    /// ```
+    pub fn set_param_bytes(&mut self, key: &str, value: &[u8]) -> Result<(), XmlmcError> {
+        self.set_param(key, &general_purpose::STANDARD.encode(value))
+    }
+
+    /// You can use this to add a boolean param, which is sent as true or false.
+    /// ```ignore
+    /// req.set_param_bool("returnMeta", true);
+    /// ```
+    pub fn set_param_bool(&mut self, key: &str, value: bool) -> Result<(), XmlmcError> {
+        self.set_param(key, if value { "true" } else { "false" })
+    }
+
+    /// You can use this to add a whole number as a param.
+    /// ```ignore
+    /// req.set_param_int("rowCount", 100);
+    /// ```
+    pub fn set_param_int(&mut self, key: &str, value: i64) -> Result<(), XmlmcError> {
+        self.set_param(key, &value.to_string())
+    }
+
+    /// You can use this to add a date and time as a param in the YYYY-MM-DD HH:MM:SS format the server uses.
+    /// The time is always sent in UTC and any fractions of a second are dropped.
+    /// ```ignore
+    /// req.set_param_datetime("dateLogged", SystemTime::now());
+    /// ```
+    pub fn set_param_datetime(&mut self, key: &str, value: SystemTime) -> Result<(), XmlmcError> {
+        self.set_param(key, &format_datetime(value))
+    }
+
+    /// You can set multiple attributes on an element as well as its value.
+    /// ```ignore
+    /// req.set_param_attr("column", "h_firstname", vec![Attributes::new("name", "firstName")]);
+    /// ```
+    pub fn set_param_attr(
+        &mut self,
+        key: &str,
+        value: &str,
+        attribs: Vec<Attributes>,
+    ) -> Result<(), XmlmcError> {
+        check_element_name(key, self.rules)?;
+        let cleaned = encode_value(key, value, self.rules)?;
+        let clean_value = String::from_utf8_lossy(cleaned.as_bytes());
+
+        let mut attrs = String::new();
+        for i in attribs {
+            check_attribute_name(&i.key, self.rules)?;
+            let cleaned_attr = encode_value(&i.key, &i.value, self.rules)?;
+            let clean_attr_value = String::from_utf8_lossy(cleaned_attr.as_bytes());
+
+            attrs.push_str(&format!(" {}=\"{}\" ", &i.key, &clean_attr_value));
+        }
+
+        self.push_params(&["<", key, &attrs, ">", &clean_value, "</", key, ">"]);
+        Ok(())
+    }
+
+    /// You can use this to add a value inside a CDATA section rather than escaping it, for things like html email
+    /// bodies that are easier to read unescaped. Any ]]> in the value is split across two sections for you.
+    /// ```ignore
+    /// req.set_param_cdata("body", "<p>Hello</p>");
+    /// ```
+    /// This will append
+    /// ```ignore
+    /// <body><![CDATA[<p>Hello</p>]]></body>
+    /// ```
+    pub fn set_param_cdata(&mut self, key: &str, value: &str) -> Result<(), XmlmcError> {
+        check_element_name(key, self.rules)?;
+        let cdata = cdata_value(key, value, self.rules)?;
+        self.push_params(&["<", key, ">", &cdata, "</", key, ">"]);
+        Ok(())
+    }
+
+    /// You can use this to add a fragment of xml you have built yourself, for example a BPM definition, as it is.
+    /// The fragment is checked first and XmlmcError::InvalidXml is returned if it is not well formed, if it leaves
+    /// an element open or closes one it did not open.
+    /// ```ignore
+    /// req.append_raw_xml("<definition><step id=\"1\"/></definition>")?;
+    /// ```
+    pub fn append_raw_xml(&mut self, xml: &str) -> Result<(), XmlmcError> {
+        check_fragment(xml, self.rules)?;
+        self.push_params(&[xml]);
+        Ok(())
+    }
+
+    /// You can use this to open an xml element in your xml output to the server
+    /// ```ignore
+    /// req.open_element("userObject");
+    /// ```
+    /// This will append
+    /// ```ignore
+    /// <userObject>
+    /// ```
+    pub fn open_element(&mut self, element: &str) -> Result<(), XmlmcError> {
+        check_element_name(element, self.rules)?;
+        self.push_params(&["<", element, ">"]);
+        self.open.push(element.to_owned());
+        Ok(())
+    }
+
+    /// You can use this to close an xml element in your xml output to the server.
+    /// The element must be the last one you opened that has not been closed yet.
+    /// ```ignore
+    /// req.close_element("userObject");
+    /// ```
+    /// This will append
+    /// ```ignore
+    /// </userObject>
+    /// ```
+    pub fn close_element(&mut self, element: &str) -> Result<(), XmlmcError> {
+        check_element_name(element, self.rules)?;
+        if self.open.last().map(String::as_str) != Some(element) {
+            return Err(XmlmcError::MismatchedElement {
+                expected: self.open.last().cloned(),
+                found: element.to_owned(),
+            });
+        }
+        self.open.pop();
+        self.push_params(&["</", element, ">"]);
+        Ok(())
+    }
+
+    /// You can use this to add an element with its children built inside the closure. The element is closed for you
+    /// so the xml is always balanced. If the closure returns an error nothing it added is kept.
+    /// ```ignore
+    /// req.element("userObject", |u| {
+    ///     u.set_param("userId", "admin")?;
+    ///     u.element("name", |n| n.set_param("first", "Admin"))
+    /// })?;
+    /// ```
+    /// This will append
+    /// ```ignore
+    /// <userObject><userId>admin</userId><name><first>Admin</first></name></userObject>
+    /// ```
+    pub fn element<F>(&mut self, element: &str, f: F) -> Result<(), XmlmcError>
+    where
+        F: FnOnce(&mut Request) -> Result<(), XmlmcError>,
+    {
+        let len = self.paramsxml.len();
+        let depth = self.open.len();
+        self.open_element(element)?;
+        if let Err(e) = f(self) {
+            self.truncate_params(len);
+            self.open.truncate(depth);
+            return Err(e);
+        }
+        //The closure may have left its own elements open so we close everything back to ours.
+        if self.open.len() != depth + 1 {
+            let unclosed = self.open.split_off(depth);
+            self.truncate_params(len);
+            self.open.truncate(depth);
+            return Err(XmlmcError::MismatchedElement {
+                expected: unclosed.last().cloned(),
+                found: element.to_owned(),
+            });
+        }
+        self.close_element(element)
+    }
+
+    /// You can use this to add the fields of any struct that implements serde::Serialize as params.
+    /// See to_params for how the struct is turned into xml.
+    /// ```ignore
+    /// req.set_params_from(&user)?;
+    /// ```
+    pub fn set_params_from<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlmcError> {
+        let xml = ser::to_params_with(value, self.rules)?;
+        self.push_params(&[&xml]);
+        Ok(())
+    }
+
+    /// You can use this to return the full xml we would be sending to the server
+    /// ```ignore
+    /// let xml_output = req.get_params();
+    /// ```
+    pub fn get_params(&self) -> String {
+        if self.paramsxml.is_empty() {
+            "".to_string()
+        } else {
+            format!("<params>{}</params>", self.paramsxml)
+        }
+    }
+
+    /// You can use this to return the xml we would be sending to the server with the values of sensitive params
+    /// replaced with [redacted], so it is safe to log.
+    /// ```ignore
+    /// println!("{}", req.get_params_redacted());
+    /// ```
+    pub fn get_params_redacted(&self) -> String {
+        if self.paramsxml.is_empty() {
+            "".to_string()
+        } else {
+            format!("<params>{}</params>", self.params_redacted())
+        }
+    }
+
+    //The params with the values of sensitive params replaced with [redacted].
+    fn params_redacted(&self) -> String {
+        let mut params = String::new();
+        let mut last = 0;
+        for &(start, end) in &self.sensitive {
+            params.push_str(&self.paramsxml[last..start]);
+            params.push_str("[redacted]");
+            last = end;
+        }
+        params.push_str(&self.paramsxml[last..]);
+        params
+    }
+
+    /// You can use this to clear the contents of the xml you would send to the server.
+    /// ```ignore
+    /// req.clear_params()
+    /// ```
+    pub fn clear_params(&mut self) {
+        if self.sensitive.is_empty() {
+            self.paramsxml = "".to_string();
+        } else {
+            self.paramsxml.zeroize();
+            self.sensitive.clear();
+        }
+        self.open.clear();
+    }
+
+    //Adds parts to the end of the params. Once they hold a sensitive value we grow the buffer ourselves, copying it
+    //into a bigger one and wiping the old one, as letting the string grow would free the old buffer with the value still in it.
+    fn push_params(&mut self, parts: &[&str]) {
+        let extra: usize = parts.iter().map(|p| p.len()).sum();
+        let len = self.paramsxml.len();
+        if !self.sensitive.is_empty() && self.paramsxml.capacity() - len < extra {
+            let mut grown = String::with_capacity((len + extra).max(self.paramsxml.capacity() * 2));
+            grown.push_str(&self.paramsxml);
+            self.paramsxml.zeroize();
+            self.paramsxml = grown;
+        }
+        for part in parts {
+            self.paramsxml.push_str(part);
+        }
+    }
+
+    //Drops everything added to the params after len, forgetting any sensitive values that went with it.
+    fn truncate_params(&mut self, len: usize) {
+        if self.sensitive.iter().any(|&(_, end)| end > len) {
+            self.paramsxml[len..].zeroize();
+        }
+        self.paramsxml.truncate(len);
+        self.sensitive.retain(|&(_, end)| end <= len);
+    }
+
+    //Makes sure every element that was opened has been closed before we send the request.
+    pub(crate) fn check_balanced(&self) -> Result<(), XmlmcError> {
+        if self.open.is_empty() {
+            return Ok(());
+        }
+        Err(XmlmcError::UnclosedElements {
+            service: self.service.clone(),
+            method: self.method.clone(),
+            elements: self.open.clone(),
+        })
+    }
+
+    //Builds the methodCall document that is posted to the server. trace is the full value of the trace attribute.
+    //The body is made at its full size up front and wiped when dropped as it holds a copy of any sensitive params.
+    pub(crate) fn body(&self, trace: &str) -> Zeroizing<String> {
+        self.build_body(trace, &self.paramsxml)
+    }
+
+    //The methodCall document with the values of sensitive params replaced with [redacted], so it can be logged.
+    pub(crate) fn body_redacted(&self, trace: &str) -> String {
+        std::mem::take(&mut *self.build_body(trace, &self.params_redacted()))
+    }
+
+    fn build_body(&self, trace: &str, params: &str) -> Zeroizing<String> {
+        let start = format!(
+            "<methodCall service=\"{}\" method=\"{}\" trace=\"{}\">",
+            self.service,
+            self.method,
+            xmlencode(trace)
+        );
+        let parts = if params.is_empty() {
+            [start.as_str(), "", "", "</methodCall>"]
+        } else {
+            [
+                start.as_str(),
+                "\n<params>",
+                params,
+                "\n</params></methodCall>",
+            ]
+        };
+        let mut body = Zeroizing::new(String::with_capacity(parts.iter().map(|p| p.len()).sum()));
+        parts.iter().for_each(|p| body.push_str(p));
+        body
+    }
+}
+
+//empty names are not valid and the name has to be valid xml
+pub(crate) fn check_element_name(name: &str, rules: Rules) -> Result<(), XmlmcError> {
+    check_name("Xml element", name, rules)
+}
+
+pub(crate) fn check_attribute_name(name: &str, rules: Rules) -> Result<(), XmlmcError> {
+    check_name("Xml attribute name", name, rules)
+}
+
+//Checks a name against the Name production of the XML 1.0 spec, https://www.w3.org/TR/xml/#NT-Name
+fn check_name(kind: &str, name: &str, rules: Rules) -> Result<(), XmlmcError> {
+    if name.is_empty() {
+        return Err(XmlmcError::invalid_name(
+            name,
+            &format!("{} cannot be empty", kind),
+        ));
+    }
+    for (i, c) in name.chars().enumerate() {
+        if rules.ascii_names && !c.is_ascii() {
+            return Err(XmlmcError::invalid_name(
+                name,
+                &format!(
+                    "{} can only contain ASCII characters, found {:?} at position {}",
+                    kind,
+                    c,
+                    i + 1
+                ),
+            ));
+        }
+        if i == 0 && !is_name_start_char(c) {
+            let reason = if is_name_char(c) {
+                format!("{} cannot start with {:?}", kind, c)
+            } else {
+                format!("{} cannot contain {:?} at position 1", kind, c)
+            };
+            return Err(XmlmcError::invalid_name(name, &reason));
+        }
+        if !is_name_char(c) {
+            return Err(XmlmcError::invalid_name(
+                name,
+                &format!("{} cannot contain {:?} at position {}", kind, c, i + 1),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        ':' | 'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}'
+        | '\u{D8}'..='\u{F6}'
+        | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}'
+        | '\u{37F}'..='\u{1FFF}'
+        | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}'
+        | '\u{2C00}'..='\u{2FEF}'
+        | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}'
+        | '\u{FDF0}'..='\u{FFFD}'
+        | '\u{10000}'..='\u{EFFFF}')
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c,
+            '-' | '.' | '0'..='9' | '\u{B7}'
+            | '\u{300}'..='\u{36F}'
+            | '\u{203F}'..='\u{2040}')
+}
+
+//Escapes a value and deals with any characters xml 1.0 does not allow as the control character policy says.
+//name is the element or attribute the value is for so the error can say where the character was.
+pub(crate) fn encode_value(name: &str, value: &str, rules: Rules) -> Result<String, XmlmcError> {
+    Ok(xmlencode(&clean_value(name, value, rules)?))
+}
+
+//Wraps a value in CDATA. A CDATA section cannot contain ]]> so we end the section between the ]] and the > and
+//start a new one.
+fn cdata_value(name: &str, value: &str, rules: Rules) -> Result<String, XmlmcError> {
+    let cleaned = clean_value(name, value, rules)?;
+    Ok(format!(
+        "<![CDATA[{}]]>",
+        cleaned.replace("]]>", "]]]]><![CDATA[>")
+    ))
+}
+
+fn clean_value<'a>(name: &str, value: &'a str, rules: Rules) -> Result<Cow<'a, str>, XmlmcError> {
+    if !value.chars().any(is_illegal_char) {
+        return Ok(Cow::Borrowed(value));
+    }
+    let mut cleaned = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        if !is_illegal_char(c) {
+            cleaned.push(c);
+            continue;
+        }
+        match rules.control_chars {
+            ControlChars::Strip => {}
+            ControlChars::Replace => cleaned.push('\u{FFFD}'),
+            ControlChars::Error => {
+                return Err(XmlmcError::IllegalCharacter {
+                    name: name.to_owned(),
+                    character: c,
+                    position: i + 1,
+                })
+            }
+        }
+    }
+    Ok(Cow::Owned(cleaned))
+}
+
+//Checks a fragment of xml is well formed on its own: every element it opens is closed, the names are valid for the
+//rules, entities are known and there are no characters xml does not allow. It cannot have a prolog or DOCTYPE.
+fn check_fragment(xml: &str, rules: Rules) -> Result<(), XmlmcError> {
+    let invalid = |reason: String| XmlmcError::InvalidXml { reason };
+    if let Some(c) = xml.chars().find(|c| is_illegal_char(*c)) {
+        return Err(invalid(format!("U+{:04X} is not allowed in xml", c as u32)));
+    }
+    let mut reader = Reader::from_str(xml);
+    let mut depth = 0usize;
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| invalid(format!("{} at position {}", e, reader.error_position())))?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                check_element_name(&String::from_utf8_lossy(e.name().as_ref()), rules)?;
+                for attr in e.attributes() {
+                    let attr = attr.map_err(|e| invalid(e.to_string()))?;
+                    check_attribute_name(&String::from_utf8_lossy(attr.key.as_ref()), rules)?;
+                    attr.unescape_value().map_err(|e| invalid(e.to_string()))?;
+                }
+                if matches!(event, Event::Start(_)) {
+                    depth += 1;
+                }
+            }
+            Event::End(_) => depth -= 1,
+            Event::Text(ref e) => {
+                //]]> is only allowed as the end of a CDATA section.
+                if e.windows(3).any(|w| w == b"]]>") {
+                    return Err(invalid("]]> is not allowed in text".to_owned()));
+                }
+                e.unescape().map_err(|e| invalid(e.to_string()))?;
+            }
+            Event::Decl(_) | Event::DocType(_) => {
+                return Err(invalid(
+                    "a fragment cannot have an xml declaration or DOCTYPE".to_owned(),
+                ))
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if depth > 0 {
+        return Err(invalid("not every element is closed".to_owned()));
+    }
+    Ok(())
+}
+
+//Anything outside the Char production of the XML 1.0 spec, https://www.w3.org/TR/xml/#NT-Char
+fn is_illegal_char(c: char) -> bool {
+    matches!(c, '\u{0}'..='\u{8}' | '\u{B}' | '\u{C}' | '\u{E}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}')
+}
+
+//Formats a time as YYYY-MM-DD HH:MM:SS in UTC. The date is worked out from the days since 1970 using
+//Howard Hinnant's days_from_civil algorithm in reverse, http://howardhinnant.github.io/date_algorithms.html
+fn format_datetime(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs_f64().ceil() as i64),
+    };
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+pub(crate) fn xmlencode(my_str: &str) -> String {
+    let mut s = String::with_capacity(my_str.len());
+
+    for c in my_str.chars() {
+        match c {
+            '<' => s.push_str("&lt;"),
+            '>' => s.push_str("&gt;"),
+            '"' => s.push_str("&quot;"),
+            '\'' => s.push_str("&apos;"),
+            '&' => s.push_str("&amp;"),
+            _ => s.push(c),
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_elements_balance() {
+        let mut req = Request::new("admin", "userCreate");
+        req.element("userObject", |u| {
+            u.set_param("userId", "admin")?;
+            u.element("name", |n| n.set_param("first", "Admin"))
+        })
+        .unwrap();
+        assert_eq!(
+            req.get_params(),
+            "<params><userObject><userId>admin</userId><name><first>Admin</first></name></userObject></params>"
+        );
+
+        //Anything added by a failing closure is thrown away.
+        let err = req
+            .element("other", |o| {
+                o.set_param("ok", "1")?;
+                o.set_param("not ok", "2")
+            })
+            .unwrap_err();
+        assert!(matches!(err, XmlmcError::InvalidName { .. }));
+        assert!(req.check_balanced().is_ok());
+
+        //Closures cannot leave their own elements open.
+        let err = req
+            .element("outer", |o| o.open_element("inner"))
+            .unwrap_err();
+        assert!(matches!(err, XmlmcError::MismatchedElement { .. }));
+        assert!(!req.get_params().contains("outer"));
+
+        req.open_element("first").unwrap();
+        let err = req.close_element("second").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Xml element \"second\" cannot be closed before \"first\""
+        );
+        let err = req.check_balanced().unwrap_err();
+        assert!(
+            matches!(err, XmlmcError::UnclosedElements { ref elements, .. } if elements == &["first"])
+        );
+        req.close_element("first").unwrap();
+        assert!(req.check_balanced().is_ok());
+    }
+
+    #[test]
+    fn test_names() {
+        let rules = Rules::default();
+        for name in [
+            "h_firstname",
+            "h.first-name",
+            "_x",
+            "ns:name",
+            "prénom",
+            "名前",
+        ] {
+            assert!(check_element_name(name, rules).is_ok(), "{}", name);
+        }
+
+        let reason = |name: &str, rules: Rules| match check_element_name(name, rules) {
+            Err(XmlmcError::InvalidName { reason, .. }) => reason,
+            _ => panic!("{} should not be valid", name),
+        };
+        assert_eq!(reason("", rules), "Xml element cannot be empty");
+        assert_eq!(reason("1abc", rules), "Xml element cannot start with '1'");
+        assert_eq!(reason("-abc", rules), "Xml element cannot start with '-'");
+        assert_eq!(
+            reason("not ok", rules),
+            "Xml element cannot contain ' ' at position 4"
+        );
+        assert_eq!(
+            reason("a\u{FFFE}", rules),
+            "Xml element cannot contain '\\u{fffe}' at position 2"
+        );
+
+        let ascii = Rules {
+            ascii_names: true,
+            ..Rules::default()
+        };
+        assert_eq!(
+            reason("prénom", ascii),
+            "Xml element can only contain ASCII characters, found 'é' at position 3"
+        );
+        assert!(check_attribute_name("h.first-name", ascii).is_ok());
+    }
+
+    #[test]
+    fn test_typed_params() {
+        let mut req = Request::new("data", "entityAddRecord");
+        req.set_param_bytes("content", b"hello").unwrap();
+        req.set_param_bool("returnModifiedData", true).unwrap();
+        req.set_param_int("count", -42).unwrap();
+        req.set_param_datetime("logged", UNIX_EPOCH + Duration::from_secs(951_827_696))
+            .unwrap();
+        req.set_param_datetime("old", UNIX_EPOCH - Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(
+            req.get_params(),
+            "<params><content>aGVsbG8=</content><returnModifiedData>true</returnModifiedData>\
+             <count>-42</count><logged>2000-02-29 12:34:56</logged><old>1969-12-31 23:59:59</old></params>"
+        );
+    }
+
+    #[test]
+    fn test_cdata_and_raw_xml() {
+        let mut req = Request::new("mail", "send");
+        req.set_param_cdata("body", "<p>a]]>b</p>").unwrap();
+        req.append_raw_xml("<to type=\"user\">admin &amp; co</to><cc/>")
+            .unwrap();
+        assert_eq!(
+            req.get_params(),
+            "<params><body><![CDATA[<p>a]]]]><![CDATA[>b</p>]]></body>\
+             <to type=\"user\">admin &amp; co</to><cc/></params>"
+        );
+
+        for bad in [
+            "<a>",
+            "</a>",
+            "<a></b>",
+            "<a x=\"1\" x=\"2\"/>",
+            "&nbsp;",
+            "<?xml version=\"1.0\"?><a/>",
+            "<1a/>",
+            "<a>]]></a>",
+            "x]]>y",
+        ] {
+            assert!(
+                matches!(
+                    req.append_raw_xml(bad),
+                    Err(XmlmcError::InvalidXml { .. }) | Err(XmlmcError::InvalidName { .. })
+                ),
+                "{}",
+                bad
+            );
+        }
+        assert!(req.get_params().ends_with("<cc/></params>"));
+    }
+
+    #[test]
+    fn test_sensitive_params() {
+        let mut req = Request::new("session", "userLogon");
+        req.set_param("userId", "admin").unwrap();
+        req.set_param_sensitive("password", "cGFzcw==").unwrap();
+        let _ = req.element("extra", |e| {
+            e.set_param_sensitive("token", "abc")?;
+            e.set_param("1bad", "x")
+        });
+        assert_eq!(
+            req.get_params(),
+            "<params><userId>admin</userId><password>cGFzcw==</password></params>"
+        );
+        assert_eq!(
+            req.get_params_redacted(),
+            "<params><userId>admin</userId><password>[redacted]</password></params>"
+        );
+        assert!(!format!("{:?}", req).contains("cGFzcw=="));
+
+        //Adding more params after a sensitive one has to grow the buffer without losing or moving anything.
+        for i in 0..64 {
+            req.set_param("group", &i.to_string()).unwrap();
+        }
+        let params = req.get_params();
+        assert!(params.starts_with(
+            "<params><userId>admin</userId><password>cGFzcw==</password><group>0</group>"
+        ));
+        assert!(params.ends_with("<group>63</group></params>"));
+        assert!(req
+            .get_params_redacted()
+            .contains("<password>[redacted]</password><group>0</group>"));
+        assert!(req.body("goApi").contains("<password>cGFzcw==</password>"));
+
+        req.clear_params();
+        req.set_param("userId", "admin").unwrap();
+        assert_eq!(req.get_params_redacted(), req.get_params());
+    }
+
+    #[test]
+    fn test_control_chars() {
+        let mut req = Request::new("admin", "userCreate");
+        req.set_param("name", "a\u{0}b\tc\u{FFFE}").unwrap();
+        req.set_control_chars(ControlChars::Strip);
+        req.set_param_attr("note", "x\u{1B}y", vec![Attributes::new("by", "\u{7}me")])
+            .unwrap();
+        assert_eq!(
+            req.get_params(),
+            "<params><name>a\u{FFFD}b\tc\u{FFFD}</name><note by=\"me\" >xy</note></params>"
+        );
+
+        req.set_control_chars(ControlChars::Error);
+        let err = req
+            .set_param_attr("note", "ok", vec![Attributes::new("by", "m\u{8}e")])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "\"by\" contains U+0008 at position 2 which is not allowed in xml"
+        );
+    }
+}
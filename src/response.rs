@@ -0,0 +1,393 @@
+use crate::{DeserializeError, XmlmcError};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::de::{self, DeserializeOwned};
+use serde::Deserialize;
+
+/// The response to a Request sent with Xmlmc::send.
+#[derive(Clone, Debug)]
+pub struct Response {
+    status: u16,
+    headers: http::header::HeaderMap,
+    body: String,
+    trace: String,
+}
+
+impl Response {
+    pub(crate) fn new(
+        status: u16,
+        mut headers: http::header::HeaderMap,
+        body: String,
+        trace: String,
+    ) -> Response {
+        hide_secret_headers(&mut headers);
+        Response {
+            status,
+            headers,
+            body,
+            trace,
+        }
+    }
+
+    /// The http status code the server returned.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// The headers the server returned.
+    pub fn headers(&self) -> &http::header::HeaderMap {
+        &self.headers
+    }
+
+    /// The response body, either xml or json depending on set_json_response.
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// The trace that was sent with the call, as it will appear in the server logs.
+    pub fn trace(&self) -> &str {
+        &self.trace
+    }
+
+    /// Consumes the response and returns the body.
+    pub fn into_body(self) -> String {
+        self.body
+    }
+}
+
+//Marks the session cookie and auth header values as sensitive so they are not shown when the headers are printed
+//with {:?}, for example in an XmlmcError.
+pub(crate) fn hide_secret_headers(headers: &mut http::header::HeaderMap) {
+    use http::header::{Entry, AUTHORIZATION, COOKIE, SET_COOKIE};
+    for name in [SET_COOKIE, COOKIE, AUTHORIZATION] {
+        if let Entry::Occupied(mut entry) = headers.entry(name) {
+            entry.iter_mut().for_each(|v| v.set_sensitive(true));
+        }
+    }
+}
+
+/// The result of invoke_as. params holds the contents of the <params> element (or "params" object in json mode)
+/// deserialized into your type.
+#[derive(Debug)]
+pub struct ApiResponse<T> {
+    /// The params returned by the server.
+    pub params: T,
+    /// The flowcode debug information the server sent back, if any.
+    pub flow_code_debug_state: Option<FlowCodeDebugState>,
+    /// The http status code the server returned.
+    pub status: u16,
+    /// The trace that was sent with the call, as it will appear in the server logs.
+    pub trace: String,
+}
+
+/// The flowCodeDebugState element some api calls return alongside their params.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct FlowCodeDebugState {
+    pub step: Option<String>,
+    #[serde(rename = "executionId")]
+    pub execution_id: Option<String>,
+}
+
+//The envelope is the same shape in xml and json once the root element is dropped.
+#[derive(Deserialize)]
+struct Envelope<T> {
+    params: Option<T>,
+    #[serde(rename = "flowCodeDebugState")]
+    flow_code_debug_state: Option<FlowCodeDebugState>,
+}
+
+//Deserializes the params of a successful response into T using xml or json depending on the mode the client is in.
+pub(crate) fn decode_response<T: DeserializeOwned>(
+    service: &str,
+    method: &str,
+    response: Response,
+    json: bool,
+) -> Result<ApiResponse<T>, XmlmcError> {
+    let decoded: Result<Envelope<T>, String> = if json {
+        serde_json::from_str(response.body()).map_err(|e| e.to_string())
+    } else {
+        quick_xml::de::from_str(response.body()).map_err(|e| e.to_string())
+    };
+    let deserialize_error = |message: String, response: Response| {
+        XmlmcError::Deserialize(Box::new(DeserializeError {
+            service: service.to_owned(),
+            method: method.to_owned(),
+            message,
+            trace: response.trace.clone(),
+            body: response.into_body(),
+        }))
+    };
+
+    let envelope = match decoded {
+        Ok(e) => e,
+        Err(message) => return Err(deserialize_error(message, response)),
+    };
+
+    //Methods that return nothing have no params element so we try and build T from nothing.
+    let params = match envelope.params {
+        Some(p) => p,
+        None => {
+            let empty = de::value::MapDeserializer::<
+                std::iter::Empty<(String, String)>,
+                de::value::Error,
+            >::new(std::iter::empty());
+            match T::deserialize(empty) {
+                Ok(p) => p,
+                Err(e) => {
+                    return Err(deserialize_error(
+                        format!("response has no params: {}", e),
+                        response,
+                    ))
+                }
+            }
+        }
+    };
+
+    Ok(ApiResponse {
+        params,
+        flow_code_debug_state: envelope.flow_code_debug_state,
+        status: response.status(),
+        trace: response.trace,
+    })
+}
+
+/// The failure details the server sends back inside the methodCallResult state element.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ApiFailure {
+    pub code: Option<String>,
+    pub message: String,
+}
+
+/// Checks a response body for a failed methodCallResult. We only return a failure when the body
+/// positively says the call failed so anything we cannot parse is passed back to the caller untouched.
+pub(crate) fn check_envelope(body: &str, json: bool) -> Option<ApiFailure> {
+    if json {
+        check_json_envelope(body)
+    } else {
+        check_xml_envelope(body)
+    }
+}
+
+//Only "@status": false decides that the call failed. The state is read as best we can as the server does not always
+//send the code as a string.
+fn check_json_envelope(body: &str) -> Option<ApiFailure> {
+    let envelope: serde_json::Value = serde_json::from_str(body).ok()?;
+    if envelope.get("@status")? != &serde_json::Value::Bool(false) {
+        return None;
+    }
+    let state = |name: &str| {
+        envelope
+            .get("state")
+            .and_then(|s| s.get(name))
+            .and_then(json_text)
+    };
+    Some(ApiFailure {
+        code: state("code"),
+        message: state("error").unwrap_or_default(),
+    })
+}
+
+fn json_text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+//Returns if the root element is a methodCallResult with status="fail", or None if it is not a methodCallResult.
+fn is_failed_root(e: &quick_xml::events::BytesStart) -> Option<bool> {
+    if e.local_name().as_ref() != b"methodCallResult" {
+        return None;
+    }
+    match e.try_get_attribute("status").ok()? {
+        Some(status) => Some(status.unescape_value().ok()? == "fail"),
+        None => Some(false),
+    }
+}
+
+fn check_xml_envelope(body: &str) -> Option<ApiFailure> {
+    let mut reader = Reader::from_str(body);
+    reader.config_mut().trim_text(true);
+
+    let mut failure = ApiFailure::default();
+    //The names of the elements we are currently inside, starting with methodCallResult.
+    let mut path: Vec<Vec<u8>> = Vec::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                if path.is_empty() && !is_failed_root(&e)? {
+                    return None;
+                }
+                path.push(e.local_name().as_ref().to_vec());
+            }
+            //A self closing <methodCallResult status="fail"/> has no state to read.
+            Ok(Event::Empty(e)) if path.is_empty() => {
+                return if is_failed_root(&e)? {
+                    Some(failure)
+                } else {
+                    None
+                };
+            }
+            Ok(Event::End(_)) => {
+                path.pop();
+                if path.is_empty() {
+                    return Some(failure);
+                }
+            }
+            Ok(Event::Text(t)) => {
+                if let Some(value) = state_child(&path) {
+                    let text = t.unescape().ok()?;
+                    set_state_value(&mut failure, value, &text);
+                }
+            }
+            Ok(Event::CData(t)) => {
+                if let Some(value) = state_child(&path) {
+                    let text = String::from_utf8_lossy(&t);
+                    set_state_value(&mut failure, value, &text);
+                }
+            }
+            //We ran out of document before methodCallResult closed so we cannot trust what we read.
+            Ok(Event::Eof) | Err(_) => return None,
+            Ok(_) => {}
+        }
+    }
+}
+
+//Returns the element name if we are directly inside methodCallResult/state/<name>
+fn state_child(path: &[Vec<u8>]) -> Option<&[u8]> {
+    if path.len() == 3 && path[1] == b"state" {
+        Some(&path[2])
+    } else {
+        None
+    }
+}
+
+fn set_state_value(failure: &mut ApiFailure, name: &[u8], text: &str) {
+    match name {
+        b"code" => failure.code = Some(text.to_owned()),
+        b"error" => failure.message.push_str(text),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_envelope() {
+        let ok =
+            "<methodCallResult status=\"ok\"><params><stage>1</stage></params></methodCallResult>";
+        assert_eq!(check_envelope(ok, false), None);
+
+        let fail = "<?xml version=\"1.0\" encoding=\"utf-8\" ?>\n<methodCallResult status=\"fail\"><state><code>0200</code><service>session</service><operation>userLogon</operation><error>Invalid &quot;user&quot;</error></state></methodCallResult>";
+        assert_eq!(
+            check_envelope(fail, false),
+            Some(ApiFailure {
+                code: Some("0200".to_owned()),
+                message: "Invalid \"user\"".to_owned(),
+            })
+        );
+
+        assert_eq!(
+            check_envelope("<methodCallResult status=\"fail\"/>", false),
+            Some(ApiFailure::default())
+        );
+        assert_eq!(
+            check_envelope("<methodCallResult status=\"ok\"/>", false),
+            None
+        );
+
+        let json_ok = r#"{"@status":true,"params":{"stage":1}}"#;
+        assert_eq!(check_envelope(json_ok, true), None);
+
+        let json_fail = r#"{"@status":false,"state":{"code":"0200","error":"Invalid user"}}"#;
+        assert_eq!(
+            check_envelope(json_fail, true),
+            Some(ApiFailure {
+                code: Some("0200".to_owned()),
+                message: "Invalid user".to_owned(),
+            })
+        );
+
+        let json_number =
+            r#"{"@status":false,"state":{"code":200,"error":"Invalid user","extra":[1]}}"#;
+        assert_eq!(
+            check_envelope(json_number, true),
+            Some(ApiFailure {
+                code: Some("200".to_owned()),
+                message: "Invalid user".to_owned(),
+            })
+        );
+        assert_eq!(
+            check_envelope(r#"{"@status":false,"state":{"error":null}}"#, true),
+            Some(ApiFailure::default())
+        );
+
+        assert_eq!(check_envelope("not xml at all", false), None);
+        assert_eq!(check_envelope("not json at all", true), None);
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct PingCheck {
+        #[serde(rename = "stageName")]
+        stage_name: String,
+        #[serde(rename = "nextStage")]
+        next_stage: i64,
+        #[serde(rename = "serviceParamsChecksum")]
+        service_params_checksum: Option<String>,
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let expected = PingCheck {
+            stage_name: "one".to_owned(),
+            next_stage: 2,
+            service_params_checksum: None,
+        };
+        let headers = http::header::HeaderMap::new();
+
+        let xml = "<methodCallResult status=\"ok\"><params><stageName>one</stageName><nextStage>2</nextStage></params>\
+                   <flowCodeDebugState><step>1</step><executionId>abc</executionId></flowCodeDebugState></methodCallResult>";
+        let res: ApiResponse<PingCheck> = decode_response(
+            "system",
+            "pingCheck",
+            Response::new(200, headers.clone(), xml.to_owned(), String::new()),
+            false,
+        )
+        .unwrap();
+        assert_eq!(res.params, expected);
+        assert_eq!(
+            res.flow_code_debug_state.unwrap().execution_id.as_deref(),
+            Some("abc")
+        );
+
+        let json = r#"{"@status":true,"params":{"stageName":"one","nextStage":2}}"#;
+        let res: ApiResponse<PingCheck> = decode_response(
+            "system",
+            "pingCheck",
+            Response::new(200, headers.clone(), json.to_owned(), String::new()),
+            true,
+        )
+        .unwrap();
+        assert_eq!(res.params, expected);
+        assert_eq!(res.flow_code_debug_state, None);
+
+        let err = decode_response::<PingCheck>(
+            "system",
+            "pingCheck",
+            Response::new(
+                200,
+                headers,
+                r#"{"@status":true}"#.to_owned(),
+                String::new(),
+            ),
+            true,
+        )
+        .unwrap_err();
+        assert!(matches!(err, XmlmcError::Deserialize(_)));
+        assert_eq!(err.body(), Some(r#"{"@status":true}"#));
+    }
+}
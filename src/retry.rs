@@ -0,0 +1,275 @@
+use crate::{Request, XmlmcError};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Controls when a failed call is sent again. Set it on the client with set_retry_policy.
+///
+/// Calls are only retried if the service and method have been marked as idempotent, as the server may have already
+/// processed a call that timed out or returned a 503. Calls that could not connect to the server at all never reached
+/// it so they are retried whatever the service and method.
+///
+/// Responses with a 429 status mean the server is throttling us and has not processed the call, so these are always
+/// retried, waiting for as long as the Retry-After header asks.
+/// ```ignore
+/// let policy = RetryPolicy::new(4)
+///     .backoff(Duration::from_millis(200), Duration::from_secs(5))
+///     .idempotent("system", "pingCheck")
+///     .idempotent("data", "*");
+/// c.set_retry_policy(policy);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    statuses: Vec<u16>,
+    transport: bool,
+    timeouts: bool,
+    throttle_attempts: u32,
+    max_retry_after: Duration,
+    //service and method pairs that are safe to send again, a method of "*" matches the whole service.
+    idempotent: Vec<(String, String)>,
+}
+
+impl Default for RetryPolicy {
+    /// The default policy makes a single attempt at a call and does not retry failures. Responses with a 429 status
+    /// are still retried up to 4 times, as set by throttle_attempts, because the server has not processed the call.
+    fn default() -> RetryPolicy {
+        RetryPolicy::new(1)
+    }
+}
+
+impl RetryPolicy {
+    /// You can use this to create a policy that makes at most max_attempts attempts at a call, including the first.
+    /// It starts with a backoff of 100ms doubling up to 10s with jitter, and retries on connection errors, timeouts
+    /// and 502, 503 and 504 responses.
+    /// ```ignore
+    /// let policy = RetryPolicy::new(3);
+    /// ```
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+            statuses: vec![502, 503, 504],
+            transport: true,
+            timeouts: true,
+            throttle_attempts: 4,
+            max_retry_after: Duration::from_secs(60),
+            idempotent: Vec::new(),
+        }
+    }
+
+    /// You can use this to set the wait before the first retry. The wait doubles for each retry after that up to max.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> RetryPolicy {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// You can use this to turn off jitter. With jitter on each wait is a random time between half and all of the backoff
+    /// so clients that failed together do not all retry together.
+    pub fn jitter(mut self, b: bool) -> RetryPolicy {
+        self.jitter = b;
+        self
+    }
+
+    /// You can use this to replace the http status codes that are retried.
+    /// ```ignore
+    /// let policy = RetryPolicy::new(3).retry_statuses(&[500, 502, 503, 504]);
+    /// ```
+    pub fn retry_statuses(mut self, statuses: &[u16]) -> RetryPolicy {
+        self.statuses = statuses.to_vec();
+        self
+    }
+
+    /// You can use this to choose if requests that failed to send, for example because the connection was reset, are retried.
+    pub fn retry_transport_errors(mut self, b: bool) -> RetryPolicy {
+        self.transport = b;
+        self
+    }
+
+    /// You can use this to choose if requests that timed out are retried.
+    pub fn retry_timeouts(mut self, b: bool) -> RetryPolicy {
+        self.timeouts = b;
+        self
+    }
+
+    /// You can use this to set how many attempts are made at a call that keeps getting a 429 response, including the first.
+    /// This is separate from max_attempts and defaults to 4. Set it to 1 to return 429 errors straight away.
+    pub fn throttle_attempts(mut self, attempts: u32) -> RetryPolicy {
+        self.throttle_attempts = attempts.max(1);
+        self
+    }
+
+    /// You can use this to set the longest Retry-After we will wait for. If the server asks us to wait longer than this
+    /// the call fails with the error so you can decide what to do. This defaults to 60 seconds.
+    pub fn max_retry_after(mut self, max: Duration) -> RetryPolicy {
+        self.max_retry_after = max;
+        self
+    }
+
+    /// You can use this to mark a service and method as safe to send more than once. Use "*" as the method to mark every
+    /// method on the service.
+    /// ```ignore
+    /// let policy = RetryPolicy::new(3).idempotent("data", "*");
+    /// ```
+    pub fn idempotent(mut self, service: &str, method: &str) -> RetryPolicy {
+        self.idempotent
+            .push((service.to_owned(), method.to_owned()));
+        self
+    }
+
+    /// The most attempts that will be made at a call, including the first.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    fn is_idempotent(&self, req: &Request) -> bool {
+        self.idempotent
+            .iter()
+            .any(|(s, m)| s == req.service() && (m == "*" || m == req.method()))
+    }
+
+    fn is_retryable(&self, req: &Request, err: &XmlmcError) -> bool {
+        match err {
+            XmlmcError::Status(e) if e.status == 429 => true,
+            //The request never reached the server so it is always safe to send again.
+            XmlmcError::Transport { source, .. } if source.is_connect() => self.transport,
+            XmlmcError::Transport { .. } => self.transport && self.is_idempotent(req),
+            XmlmcError::Timeout { .. } => self.timeouts && self.is_idempotent(req),
+            XmlmcError::Status(e) => self.statuses.contains(&e.status) && self.is_idempotent(req),
+            _ => false,
+        }
+    }
+
+    //Throttled calls get their own number of attempts.
+    fn limit(&self, err: &XmlmcError) -> u32 {
+        match err.status_code() {
+            Some(429) => self.throttle_attempts,
+            _ => self.max_attempts,
+        }
+    }
+
+    //How long to wait before the next attempt, or None if the call should not be retried.
+    //attempt is the number of attempts that have already been made.
+    pub(crate) fn next_delay(
+        &self,
+        req: &Request,
+        err: &XmlmcError,
+        attempt: u32,
+    ) -> Option<Duration> {
+        if attempt >= self.limit(err) || !self.is_retryable(req, err) {
+            return None;
+        }
+        if let Some(wait) = err.retry_after() {
+            return if wait <= self.max_retry_after {
+                Some(wait)
+            } else {
+                None
+            };
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_backoff);
+        if !self.jitter {
+            return Some(backoff);
+        }
+        let half = backoff / 2;
+        let spread = half.as_nanos() as u64;
+        let extra = if spread == 0 { 0 } else { random() % spread };
+        Some(half + Duration::from_nanos(extra))
+    }
+
+    //Wraps the final error with the number of attempts if we gave up because we ran out of them.
+    pub(crate) fn exhausted(&self, req: &Request, err: XmlmcError, attempts: u32) -> XmlmcError {
+        if attempts > 1 && attempts >= self.limit(&err) && self.is_retryable(req, &err) {
+            XmlmcError::RetriesExhausted {
+                attempts,
+                source: Box::new(err),
+            }
+        } else {
+            err
+        }
+    }
+}
+
+//Reads the Retry-After header, which is either a number of seconds or a http date.
+pub(crate) fn retry_after(headers: &http::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(http::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+//The lowest rate limit we allow, one request an hour. Anything lower would have callers waiting for days.
+pub(crate) const MIN_RATE: f64 = 1.0 / 3600.0;
+
+//A token bucket shared between all clones of a client so they stay under a request rate together.
+pub(crate) struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(per_second: f64, burst: u32) -> TokenBucket {
+        let burst = f64::from(burst.max(1));
+        TokenBucket {
+            rate: per_second.max(MIN_RATE),
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    //Takes a token and returns how long the caller has to wait before using it. Tokens can go negative so callers
+    //that are waiting queue up behind each other rather than all waking at once.
+    pub(crate) fn reserve(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+//Counts calls to random so two in the same instant still differ.
+static RANDOM_CALLS: AtomicU64 = AtomicU64::new(0);
+
+//A random number for jitter and trace ids without pulling in a rng. RandomState is only seeded randomly once per thread
+//and after that its keys are just incremented, so we also hash a process wide counter, the time and the process id.
+//That makes the ids different between calls, threads and processes, though as they are 64 bit hashes a clash is
+//unlikely rather than impossible.
+pub(crate) fn random() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(RANDOM_CALLS.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.write_u32(std::process::id());
+    hasher.finish()
+}
@@ -0,0 +1,737 @@
+use crate::request::{check_attribute_name, check_element_name, encode_value, Rules};
+use crate::XmlmcError;
+use base64::{engine::general_purpose, Engine as _};
+use serde::ser::{self, Serialize};
+
+/// You can use this to turn any struct that implements serde::Serialize into the xml that goes inside <params>.
+///
+/// - Each field becomes an element named after the field (use #[serde(rename = "...")] to change it).
+/// - Nested structs and maps become nested elements.
+/// - Vecs become the same element repeated once for each item.
+/// - Option::None fields are left out.
+/// - Fields renamed to start with @ become attributes on the parent element and a field renamed to $value becomes its text.
+/// - Byte fields (with serde_bytes) are base64 encoded.
+///
+/// ```ignore
+/// #[derive(Serialize)]
+/// struct Column {
+///     #[serde(rename = "@name")]
+///     name: String,
+///     #[serde(rename = "$value")]
+///     value: String,
+/// }
+///
+/// #[derive(Serialize)]
+/// struct UserCreate {
+///     #[serde(rename = "userId")]
+///     user_id: String,
+///     #[serde(rename = "column")]
+///     columns: Vec<Column>,
+///     notes: Option<String>,
+/// }
+///
+/// let xml = to_params(&user)?;
+/// //<userId>jbloggs</userId><column name="h_firstname">Joe</column><column name="h_lastname">Bloggs</column>
+/// ```
+pub fn to_params<T: Serialize + ?Sized>(value: &T) -> Result<String, XmlmcError> {
+    to_params_with(value, Rules::default())
+}
+
+//to_params with the rules of the request the params are being added to.
+pub(crate) fn to_params_with<T: Serialize + ?Sized>(
+    value: &T,
+    rules: Rules,
+) -> Result<String, XmlmcError> {
+    let mut out = String::new();
+    value.serialize(ParamsSerializer {
+        out: &mut out,
+        rules,
+    })?;
+    Ok(out)
+}
+
+impl ser::Error for XmlmcError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        XmlmcError::Serialize(msg.to_string())
+    }
+}
+
+fn unsupported(what: &str) -> XmlmcError {
+    XmlmcError::Serialize(format!("{} cannot be serialized into params", what))
+}
+
+//The top level value has to be a struct or map whose fields become the elements inside <params>.
+struct ParamsSerializer<'a> {
+    out: &'a mut String,
+    rules: Rules,
+}
+
+macro_rules! top_level_unsupported {
+    ($($method:ident($($ty:ty),*)),* $(,)?) => {
+        $(
+            fn $method(self, $(_: $ty),*) -> Result<Self::Ok, Self::Error> {
+                Err(unsupported("only a struct or map can be used for params, a value"))
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for ParamsSerializer<'a> {
+    type Ok = ();
+    type Error = XmlmcError;
+    type SerializeSeq = ser::Impossible<(), XmlmcError>;
+    type SerializeTuple = ser::Impossible<(), XmlmcError>;
+    type SerializeTupleStruct = ser::Impossible<(), XmlmcError>;
+    type SerializeTupleVariant = ser::Impossible<(), XmlmcError>;
+    type SerializeMap = StructSerializer<'a>;
+    type SerializeStruct = StructSerializer<'a>;
+    type SerializeStructVariant = ser::Impossible<(), XmlmcError>;
+
+    top_level_unsupported!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    );
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), XmlmcError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), XmlmcError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), XmlmcError> {
+        Err(unsupported("an enum"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, XmlmcError> {
+        Err(unsupported("a sequence without an element name"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, XmlmcError> {
+        Err(unsupported("a tuple without an element name"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, XmlmcError> {
+        Err(unsupported("a tuple struct without an element name"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, XmlmcError> {
+        Err(unsupported("an enum"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, XmlmcError> {
+        Ok(StructSerializer::new(self.out, None, self.rules))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, XmlmcError> {
+        Ok(StructSerializer::new(self.out, None, self.rules))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, XmlmcError> {
+        Err(unsupported("an enum"))
+    }
+}
+
+//Writes a value as one or more elements called name.
+struct FieldSerializer<'a> {
+    out: &'a mut String,
+    name: &'a str,
+    rules: Rules,
+}
+
+impl FieldSerializer<'_> {
+    fn write_text(self, text: &str) -> Result<(), XmlmcError> {
+        self.out.push_str(&format!(
+            "<{}>{}</{}>",
+            self.name,
+            encode_value(self.name, text, self.rules)?,
+            self.name
+        ));
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for FieldSerializer<'a> {
+    type Ok = ();
+    type Error = XmlmcError;
+    type SerializeSeq = SeqSerializer<'a>;
+    type SerializeTuple = SeqSerializer<'a>;
+    type SerializeTupleStruct = SeqSerializer<'a>;
+    type SerializeTupleVariant = ser::Impossible<(), XmlmcError>;
+    type SerializeMap = StructSerializer<'a>;
+    type SerializeStruct = StructSerializer<'a>;
+    type SerializeStructVariant = ser::Impossible<(), XmlmcError>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), XmlmcError> {
+        self.write_text(if v { "true" } else { "false" })
+    }
+    fn serialize_i8(self, v: i8) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_i16(self, v: i16) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_i32(self, v: i32) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_i64(self, v: i64) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_u8(self, v: u8) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_u16(self, v: u16) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_u32(self, v: u32) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_u64(self, v: u64) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_f32(self, v: f32) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_f64(self, v: f64) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_char(self, v: char) -> Result<(), XmlmcError> {
+        self.write_text(&v.to_string())
+    }
+    fn serialize_str(self, v: &str) -> Result<(), XmlmcError> {
+        self.write_text(v)
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), XmlmcError> {
+        self.write_text(&general_purpose::STANDARD.encode(v))
+    }
+
+    fn serialize_none(self) -> Result<(), XmlmcError> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), XmlmcError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), XmlmcError> {
+        self.write_text("")
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), XmlmcError> {
+        self.write_text("")
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), XmlmcError> {
+        self.write_text(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), XmlmcError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), XmlmcError> {
+        check_element_name(variant, self.rules)?;
+        self.out.push_str(&format!("<{}>", self.name));
+        value.serialize(FieldSerializer {
+            out: &mut *self.out,
+            name: variant,
+            rules: self.rules,
+        })?;
+        self.out.push_str(&format!("</{}>", self.name));
+        Ok(())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, XmlmcError> {
+        Ok(SeqSerializer {
+            out: self.out,
+            name: self.name,
+            rules: self.rules,
+        })
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, XmlmcError> {
+        Ok(SeqSerializer {
+            out: self.out,
+            name: self.name,
+            rules: self.rules,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, XmlmcError> {
+        Ok(SeqSerializer {
+            out: self.out,
+            name: self.name,
+            rules: self.rules,
+        })
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, XmlmcError> {
+        Err(unsupported("a tuple enum variant"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, XmlmcError> {
+        Ok(StructSerializer::new(self.out, Some(self.name), self.rules))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, XmlmcError> {
+        Ok(StructSerializer::new(self.out, Some(self.name), self.rules))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, XmlmcError> {
+        Err(unsupported("a struct enum variant"))
+    }
+}
+
+//Repeats the element for each item in a Vec.
+struct SeqSerializer<'a> {
+    out: &'a mut String,
+    name: &'a str,
+    rules: Rules,
+}
+
+impl SeqSerializer<'_> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlmcError> {
+        value.serialize(FieldSerializer {
+            out: &mut *self.out,
+            name: self.name,
+            rules: self.rules,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer<'_> {
+    type Ok = ();
+    type Error = XmlmcError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlmcError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), XmlmcError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer<'_> {
+    type Ok = ();
+    type Error = XmlmcError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlmcError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), XmlmcError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer<'_> {
+    type Ok = ();
+    type Error = XmlmcError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlmcError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), XmlmcError> {
+        Ok(())
+    }
+}
+
+//Collects the attributes, text and child elements of a struct or map and writes them out once we have them all.
+//A name of None is the top level where the children go straight into <params>.
+struct StructSerializer<'a> {
+    out: &'a mut String,
+    name: Option<&'a str>,
+    attrs: String,
+    text: String,
+    children: String,
+    key: Option<String>,
+    rules: Rules,
+}
+
+impl<'a> StructSerializer<'a> {
+    fn new(out: &'a mut String, name: Option<&'a str>, rules: Rules) -> StructSerializer<'a> {
+        StructSerializer {
+            out,
+            name,
+            rules,
+            attrs: String::new(),
+            text: String::new(),
+            children: String::new(),
+            key: None,
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), XmlmcError> {
+        if let Some(attr) = key.strip_prefix('@') {
+            check_attribute_name(attr, self.rules)?;
+            if self.name.is_none() {
+                return Err(unsupported("an attribute on <params>"));
+            }
+            if let Some(v) = value.serialize(TextSerializer)? {
+                let v = encode_value(attr, &v, self.rules)?;
+                self.attrs.push_str(&format!(" {}=\"{}\"", attr, v));
+            }
+        } else if key == "$value" {
+            let name = match self.name {
+                Some(n) => n,
+                None => return Err(unsupported("text directly inside <params>")),
+            };
+            if let Some(v) = value.serialize(TextSerializer)? {
+                self.text.push_str(&encode_value(name, &v, self.rules)?);
+            }
+        } else {
+            check_element_name(key, self.rules)?;
+            value.serialize(FieldSerializer {
+                out: &mut self.children,
+                name: key,
+                rules: self.rules,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), XmlmcError> {
+        match self.name {
+            Some(name) => self.out.push_str(&format!(
+                "<{}{}>{}{}</{}>",
+                name, self.attrs, self.text, self.children, name
+            )),
+            None => self.out.push_str(&self.children),
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for StructSerializer<'_> {
+    type Ok = ();
+    type Error = XmlmcError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), XmlmcError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), XmlmcError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeMap for StructSerializer<'_> {
+    type Ok = ();
+    type Error = XmlmcError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), XmlmcError> {
+        match key.serialize(TextSerializer)? {
+            Some(k) => {
+                self.key = Some(k);
+                Ok(())
+            }
+            None => Err(unsupported("a map key of None")),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlmcError> {
+        let key = self.key.take().unwrap_or_default();
+        self.field(&key, value)
+    }
+
+    fn end(self) -> Result<(), XmlmcError> {
+        self.finish()
+    }
+}
+
+//Turns a simple value into text for attributes, $value fields and map keys. None means leave it out.
+struct TextSerializer;
+
+impl ser::Serializer for TextSerializer {
+    type Ok = Option<String>;
+    type Error = XmlmcError;
+    type SerializeSeq = ser::Impossible<Option<String>, XmlmcError>;
+    type SerializeTuple = ser::Impossible<Option<String>, XmlmcError>;
+    type SerializeTupleStruct = ser::Impossible<Option<String>, XmlmcError>;
+    type SerializeTupleVariant = ser::Impossible<Option<String>, XmlmcError>;
+    type SerializeMap = ser::Impossible<Option<String>, XmlmcError>;
+    type SerializeStruct = ser::Impossible<Option<String>, XmlmcError>;
+    type SerializeStructVariant = ser::Impossible<Option<String>, XmlmcError>;
+
+    fn serialize_bool(self, v: bool) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(if v { "true" } else { "false" }.to_owned()))
+    }
+    fn serialize_i8(self, v: i8) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_i16(self, v: i16) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_i32(self, v: i32) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_i64(self, v: i64) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_u8(self, v: u8) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_u16(self, v: u16) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_u32(self, v: u32) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_u64(self, v: u64) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_f32(self, v: f32) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_f64(self, v: f64) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_char(self, v: char) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_string()))
+    }
+    fn serialize_str(self, v: &str) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(v.to_owned()))
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(general_purpose::STANDARD.encode(v)))
+    }
+    fn serialize_none(self) -> Result<Option<String>, XmlmcError> {
+        Ok(None)
+    }
+    fn serialize_some<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Option<String>, XmlmcError> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(String::new()))
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(String::new()))
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Option<String>, XmlmcError> {
+        Ok(Some(variant.to_owned()))
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Option<String>, XmlmcError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Option<String>, XmlmcError> {
+        Err(unsupported("an enum with data in an attribute or text"))
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, XmlmcError> {
+        Err(unsupported("a sequence in an attribute or text"))
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, XmlmcError> {
+        Err(unsupported("a tuple in an attribute or text"))
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, XmlmcError> {
+        Err(unsupported("a tuple struct in an attribute or text"))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, XmlmcError> {
+        Err(unsupported("an enum with data in an attribute or text"))
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, XmlmcError> {
+        Err(unsupported("a map in an attribute or text"))
+    }
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, XmlmcError> {
+        Err(unsupported("a struct in an attribute or text"))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, XmlmcError> {
+        Err(unsupported("an enum with data in an attribute or text"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Column {
+        #[serde(rename = "@name")]
+        name: &'static str,
+        #[serde(rename = "$value")]
+        value: &'static str,
+    }
+
+    #[derive(Serialize)]
+    struct Address {
+        line1: &'static str,
+        postcode: Option<&'static str>,
+    }
+
+    #[derive(Serialize)]
+    struct UserCreate {
+        #[serde(rename = "userId")]
+        user_id: &'static str,
+        enabled: bool,
+        count: u32,
+        notes: Option<&'static str>,
+        address: Address,
+        #[serde(rename = "column")]
+        columns: Vec<Column>,
+    }
+
+    #[test]
+    fn test_to_params() {
+        let user = UserCreate {
+            user_id: "jbloggs",
+            enabled: true,
+            count: 3,
+            notes: None,
+            address: Address {
+                line1: "1 High St & Co",
+                postcode: None,
+            },
+            columns: vec![
+                Column {
+                    name: "h_firstname",
+                    value: "Joe",
+                },
+                Column {
+                    name: "h_lastname",
+                    value: "O'Bloggs",
+                },
+            ],
+        };
+        assert_eq!(
+            to_params(&user).unwrap(),
+            "<userId>jbloggs</userId><enabled>true</enabled><count>3</count>\
+             <address><line1>1 High St &amp; Co</line1></address>\
+             <column name=\"h_firstname\">Joe</column><column name=\"h_lastname\">O&apos;Bloggs</column>"
+        );
+
+        let mut map = BTreeMap::new();
+        map.insert("bad name", 1);
+        assert!(matches!(
+            to_params(&map),
+            Err(XmlmcError::InvalidName { .. })
+        ));
+        assert!(matches!(to_params(&1), Err(XmlmcError::Serialize(_))));
+    }
+}
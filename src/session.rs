@@ -0,0 +1,244 @@
+use crate::{response, HttpClient, Request, Response, Xmlmc, XmlmcClient, XmlmcError};
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use zeroize::{Zeroize, Zeroizing};
+
+/// A user id and password used to log on to your instance with session::userLogon.
+/// The password is wiped from memory when the credentials are dropped and is not shown by Debug.
+#[derive(Clone)]
+pub struct Credentials {
+    pub user_id: String,
+    pub password: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("user_id", &self.user_id)
+            .field("password", &"[redacted]")
+            .finish()
+    }
+}
+
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        self.password.zeroize();
+    }
+}
+
+impl Credentials {
+    /// You can use this to create credentials from a user id and a plain text password.
+    /// ```ignore
+    /// let creds = Credentials::new("admin", "password");
+    /// ```
+    pub fn new(user_id: &str, password: &str) -> Credentials {
+        Credentials {
+            user_id: user_id.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+}
+
+/// Something that can hand out the credentials to log back on with when a session expires.
+/// This is implemented for Credentials and for any closure returning Option<Credentials> so the password can be
+/// fetched from a vault or prompt only when it is needed. Returning None leaves the original error in place.
+pub trait CredentialsProvider: Send + Sync {
+    fn credentials(&self) -> Option<Credentials>;
+}
+
+impl CredentialsProvider for Credentials {
+    fn credentials(&self) -> Option<Credentials> {
+        Some(self.clone())
+    }
+}
+
+impl<F> CredentialsProvider for F
+where
+    F: Fn() -> Option<Credentials> + Send + Sync,
+{
+    fn credentials(&self) -> Option<Credentials> {
+        self()
+    }
+}
+
+/// The details of the session returned by user_logon and guest_logon.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SessionInfo {
+    #[serde(rename = "sessionId")]
+    pub session_id: Option<String>,
+    #[serde(rename = "userId")]
+    pub user_id: Option<String>,
+    #[serde(rename = "maxIdleTime")]
+    pub max_idle_time: Option<String>,
+    #[serde(rename = "timeZone")]
+    pub time_zone: Option<String>,
+    pub language: Option<String>,
+}
+
+//How the session the client is holding was created, so we know which logoff to call.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LogonKind {
+    User,
+    Guest,
+}
+
+impl LogonKind {
+    fn logoff_method(self) -> &'static str {
+        match self {
+            LogonKind::User => "userLogoff",
+            LogonKind::Guest => "guestLogoff",
+        }
+    }
+}
+
+pub(crate) type LogoffOnDrop = Box<dyn FnOnce(&str, LogonKind) + Send>;
+
+//Builds the session::userLogon request. The server wants the password base64 encoded.
+pub(crate) fn logon_request(creds: &Credentials) -> Result<Request, XmlmcError> {
+    let mut req = Request::new("session", "userLogon");
+    req.set_param("userId", &creds.user_id)?;
+    let password = Zeroizing::new(general_purpose::STANDARD.encode(&creds.password));
+    req.set_param_sensitive("password", &password)?;
+    Ok(req)
+}
+
+pub(crate) fn guest_logon_request(guest_id: &str, guest_name: &str) -> Result<Request, XmlmcError> {
+    let mut req = Request::new("session", "guestLogon");
+    req.set_param("guestId", guest_id)?;
+    req.set_param("guestName", guest_name)?;
+    Ok(req)
+}
+
+//We should never try and log back on because a logon itself failed.
+pub(crate) fn can_relogon(req: &Request, err: &XmlmcError) -> bool {
+    req.service() != "session" && err.is_session_expired()
+}
+
+impl<C: HttpClient> XmlmcClient<C> {
+    //Reads the logon response and remembers that we created this session.
+    pub(crate) fn finish_logon(
+        &self,
+        req: &Request,
+        response: Response,
+        kind: LogonKind,
+    ) -> Result<SessionInfo, XmlmcError> {
+        let info: SessionInfo =
+            response::decode_response(req.service(), req.method(), response, self.jsonresp)?.params;
+
+        //The cookie is normally set for us but fall back to the session id in the response if it was not.
+        if let Some(id) = &info.session_id {
+            let cookie = format!("ESPSessionState={}", id);
+            let mut session = self.shared.session_id.write().unwrap();
+            if !session.contains(id.as_str()) {
+                *session = Zeroizing::new(cookie);
+            }
+        }
+        *self.shared.owned.lock().unwrap() = Some((self.session_id(), kind));
+        Ok(info)
+    }
+
+    //The logoff request for the current session, if this client created it.
+    pub(crate) fn owned_logoff(&self) -> Option<Request> {
+        match &*self.shared.owned.lock().unwrap() {
+            Some((session, kind)) if *session == self.session_id() => {
+                Some(Request::new("session", kind.logoff_method()))
+            }
+            _ => None,
+        }
+    }
+
+    //Forgets the session once it has been logged off.
+    pub(crate) fn finish_logoff(&self) {
+        self.shared.session_id.write().unwrap().zeroize();
+        *self.shared.owned.lock().unwrap() = None;
+    }
+}
+
+impl Xmlmc {
+    /// You can use this to log on to your instance with session::userLogon. The password is base64 encoded for you
+    /// and the new session is saved on the client (and any clones of it) for all calls after this.
+    /// ```ignore
+    /// let info = c.user_logon("admin", "password")?;
+    /// println!("{:?}", info.session_id);
+    /// ```
+    pub fn user_logon(&self, user_id: &str, password: &str) -> Result<SessionInfo, XmlmcError> {
+        let req = logon_request(&Credentials::new(user_id, password))?;
+        let response = self.send(&req)?;
+        self.finish_logon(&req, response, LogonKind::User)
+    }
+
+    /// You can use this to log on to your instance as a guest with session::guestLogon.
+    /// ```ignore
+    /// let info = c.guest_logon("guest1", "Guest User")?;
+    /// ```
+    pub fn guest_logon(&self, guest_id: &str, guest_name: &str) -> Result<SessionInfo, XmlmcError> {
+        let req = guest_logon_request(guest_id, guest_name)?;
+        let response = self.send(&req)?;
+        self.finish_logon(&req, response, LogonKind::Guest)
+    }
+
+    /// You can use this to log off the current session. Guest sessions created with guest_logon are logged off
+    /// with session::guestLogoff, anything else with session::userLogoff.
+    /// ```ignore
+    /// c.logoff()?;
+    /// ```
+    pub fn logoff(&self) -> Result<(), XmlmcError> {
+        let req = self
+            .owned_logoff()
+            .unwrap_or_else(|| Request::new("session", "userLogoff"));
+        self.send(&req)?;
+        self.finish_logoff();
+        Ok(())
+    }
+
+    /// You can use this to log off the session only if it was created by user_logon or guest_logon on this client.
+    /// Sessions you set with set_sessionid are left alone.
+    /// ```ignore
+    /// c.close()?;
+    /// ```
+    pub fn close(&self) -> Result<(), XmlmcError> {
+        if let Some(req) = self.owned_logoff() {
+            self.send(&req)?;
+            self.finish_logoff();
+        }
+        Ok(())
+    }
+
+    /// You can use this to check if the current session is still valid. This calls session::getSessionInfo and
+    /// returns false if the server says the session is invalid or has expired.
+    /// ```ignore
+    /// if !c.is_session_valid()? {
+    ///     c.user_logon("admin", "password")?;
+    /// }
+    /// ```
+    pub fn is_session_valid(&self) -> Result<bool, XmlmcError> {
+        match self.send(&Request::new("session", "getSessionInfo")) {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_session_expired() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// You can use this to log off the session when the last clone of this client is dropped, if the session was
+    /// created by user_logon or guest_logon on this client. Use close() if you want to see any error from the logoff.
+    /// ```ignore
+    /// c.set_logoff_on_drop(true);
+    /// ```
+    pub fn set_logoff_on_drop(&mut self, b: bool) {
+        let logoff: Option<LogoffOnDrop> = if b {
+            //The closure lives inside the shared state so it needs its own copy of the client that does not point back at it.
+            let mut detached = self.clone();
+            detached.shared = Arc::default();
+            detached.credentials = None;
+            Some(Box::new(move |session: &str, kind: LogonKind| {
+                detached.set_sessionid(session);
+                let _ = detached.send(&Request::new("session", kind.logoff_method()));
+            }))
+        } else {
+            None
+        };
+        *self.shared.logoff_on_drop.lock().unwrap() = logoff;
+    }
+}
@@ -0,0 +1,297 @@
+//Spans and events for api calls when the tracing feature is on. Without the feature everything here does nothing so
+//the call paths do not need to check for it.
+use crate::{Response, XmlmcError};
+use std::time::Instant;
+
+#[cfg(feature = "tracing")]
+use quick_xml::events::{BytesStart, BytesText, Event};
+#[cfg(feature = "tracing")]
+use quick_xml::{Reader, Writer};
+
+#[cfg(all(feature = "tracing", feature = "async"))]
+pub(crate) use tracing::Instrument;
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn enter(&self) -> &Span {
+        self
+    }
+}
+
+#[cfg(all(not(feature = "tracing"), feature = "async"))]
+pub(crate) trait Instrument: Sized {
+    fn instrument(self, _span: Span) -> Self {
+        self
+    }
+}
+
+#[cfg(all(not(feature = "tracing"), feature = "async"))]
+impl<T> Instrument for T {}
+
+//Element, attribute and json key names containing any of these have their values hidden in logged bodies.
+#[cfg(feature = "tracing")]
+const SENSITIVE: &[&str] = &[
+    "password",
+    "secret",
+    "token",
+    "apikey",
+    "sessionid",
+    "credential",
+    "authorization",
+];
+
+#[cfg(feature = "tracing")]
+const REDACTED: &str = "[redacted]";
+
+//The span every api call runs in. The empty fields are filled in as the call goes along.
+#[cfg(feature = "tracing")]
+pub(crate) fn call_span(service: &str, method: &str, trace: &str) -> Span {
+    use tracing::field::Empty;
+    tracing::info_span!(
+        "xmlmc.invoke",
+        service,
+        method,
+        trace,
+        status = Empty,
+        attempts = Empty,
+        request_bytes = Empty,
+        response_bytes = Empty,
+        latency_ms = Empty,
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn call_span(_service: &str, _method: &str, _trace: &str) -> Span {
+    Span
+}
+
+//Called before each attempt at sending the request, starting from 1.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn attempt(attempt: u32) {
+    #[cfg(feature = "tracing")]
+    {
+        Span::current().record("attempts", attempt);
+        tracing::debug!(attempt, "sending request");
+    }
+}
+
+//size is the size of the body that is sent. redacted is only called if the body is going to be logged, and should
+//return it with the values of params marked as sensitive hidden.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn request(size: usize, redacted: impl FnOnce() -> String) {
+    #[cfg(feature = "tracing")]
+    {
+        Span::current().record("request_bytes", size);
+        tracing::trace!(body = %redact(redacted().as_bytes()), "request body");
+    }
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn response(status: u16, body: &[u8]) {
+    #[cfg(feature = "tracing")]
+    {
+        let span = Span::current();
+        span.record("status", status);
+        span.record("response_bytes", body.len());
+        tracing::trace!(status, body = %redact(body), "response body");
+    }
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn finish(span: &Span, start: Instant, result: &Result<Response, XmlmcError>) {
+    #[cfg(feature = "tracing")]
+    {
+        let latency = start.elapsed().as_millis() as u64;
+        span.record("latency_ms", latency);
+        match result {
+            Ok(_) => tracing::debug!(parent: span, latency_ms = latency, "call succeeded"),
+            Err(e) => {
+                tracing::warn!(parent: span, latency_ms = latency, error = %chain(e), "call failed")
+            }
+        }
+    }
+}
+
+//The error and all of its causes on one line.
+#[cfg(feature = "tracing")]
+fn chain(e: &XmlmcError) -> String {
+    let mut message = e.to_string();
+    let mut cause = std::error::Error::source(e);
+    while let Some(c) = cause {
+        message.push_str(": ");
+        message.push_str(&c.to_string());
+        cause = c.source();
+    }
+    message
+}
+
+//Hides the values of sensitive elements, attributes and json keys so bodies can be logged.
+#[cfg(feature = "tracing")]
+fn redact(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body);
+    let redacted = if text.trim_start().starts_with('{') {
+        redact_json(&text)
+    } else {
+        redact_xml(&text)
+    };
+    redacted.unwrap_or_else(|| format!("<{} bytes that could not be parsed>", body.len()))
+}
+
+#[cfg(feature = "tracing")]
+fn is_sensitive(name: &[u8]) -> bool {
+    let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+    SENSITIVE.iter().any(|s| name.contains(s))
+}
+
+#[cfg(feature = "tracing")]
+fn redact_json(text: &str) -> Option<String> {
+    use serde_json::Value;
+    fn walk(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, v) in map.iter_mut() {
+                    if is_sensitive(key.as_bytes()) {
+                        *v = Value::String(REDACTED.to_owned());
+                    } else {
+                        walk(v);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(walk),
+            _ => {}
+        }
+    }
+    let mut value = serde_json::from_str(text).ok()?;
+    walk(&mut value);
+    serde_json::to_string(&value).ok()
+}
+
+#[cfg(feature = "tracing")]
+fn redact_xml(text: &str) -> Option<String> {
+    let mut reader = Reader::from_str(text);
+    let mut writer = Writer::new(Vec::new());
+    //How deep we are inside a sensitive element, everything in it is hidden.
+    let mut hidden = 0usize;
+    loop {
+        let event = match reader.read_event().ok()? {
+            Event::Eof => break,
+            Event::Start(e) => {
+                if hidden > 0 || is_sensitive(e.local_name().as_ref()) {
+                    hidden += 1;
+                }
+                Event::Start(redact_attributes(&e)?)
+            }
+            Event::Empty(e) => Event::Empty(redact_attributes(&e)?),
+            Event::End(e) => {
+                hidden = hidden.saturating_sub(1);
+                Event::End(e)
+            }
+            Event::Text(_) | Event::CData(_) if hidden > 0 => Event::Text(BytesText::new(REDACTED)),
+            other => other,
+        };
+        writer.write_event(event).ok()?;
+    }
+    String::from_utf8(writer.into_inner()).ok()
+}
+
+#[cfg(feature = "tracing")]
+fn redact_attributes(e: &BytesStart) -> Option<BytesStart<'static>> {
+    let mut out = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+    for attr in e.attributes() {
+        let attr = attr.ok()?;
+        if is_sensitive(attr.key.local_name().as_ref()) {
+            out.push_attribute((attr.key.as_ref(), REDACTED.as_bytes()));
+        } else {
+            out.push_attribute(attr);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+    use crate::{test_server, Request, Xmlmc};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::{span, Event, Metadata};
+
+    //Keeps the fields of every event so the tests can check what would have been logged.
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.push_str(&format!("{}={:?} ", field.name(), value));
+        }
+    }
+
+    impl tracing::Subscriber for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+        fn enter(&self, _: &span::Id) {}
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn test_sensitive_params_not_logged() {
+        let ok = "<methodCallResult status=\"ok\"></methodCallResult>";
+        let server = test_server::serve(vec![test_server::response(200, &[], ok)]);
+        let x = Xmlmc::new(&server.url).unwrap();
+        let mut req = Request::new("admin", "userCreate");
+        req.set_param("userId", "jbloggs").unwrap();
+        req.set_param_sensitive("h_ssn", "078-05-1120").unwrap();
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Capture(logs.clone()), || x.send(&req).unwrap());
+
+        let logs = logs.lock().unwrap().join("\n");
+        assert!(logs.contains("<h_ssn>[redacted]</h_ssn>"), "{}", logs);
+        assert!(logs.contains("<userId>jbloggs</userId>"));
+        assert!(!logs.contains("078-05-1120"));
+        assert!(server
+            .requests
+            .recv()
+            .unwrap()
+            .contains("<h_ssn>078-05-1120</h_ssn>"));
+    }
+
+    #[test]
+    fn test_redact() {
+        let xml = "<methodCall service=\"session\" method=\"userLogon\" trace=\"goApi\">\n<params>\
+                   <userId>admin</userId><password>cGFzc3dvcmQ=</password>\
+                   <h apiKey=\"123\" name=\"x\"/><secret><a>1</a></secret></params></methodCall>";
+        assert_eq!(
+            redact(xml.as_bytes()),
+            "<methodCall service=\"session\" method=\"userLogon\" trace=\"goApi\">\n<params>\
+             <userId>admin</userId><password>[redacted]</password>\
+             <h apiKey=\"[redacted]\" name=\"x\"/><secret><a>[redacted]</a></secret></params></methodCall>"
+        );
+
+        let json = r#"{"@status":true,"params":{"sessionId":"abc","userId":"admin"}}"#;
+        assert_eq!(
+            redact(json.as_bytes()),
+            r#"{"@status":true,"params":{"sessionId":"[redacted]","userId":"admin"}}"#
+        );
+
+        assert_eq!(redact(b"<a></b>"), "<7 bytes that could not be parsed>");
+    }
+}
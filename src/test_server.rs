@@ -0,0 +1,65 @@
+//A tiny http server for the tests so we can check how the client handles responses without a hornbill instance.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+pub(crate) struct TestServer {
+    pub url: String,
+    //Each request the server received, headers and body.
+    pub requests: mpsc::Receiver<String>,
+}
+
+//Builds a raw http response that closes the connection once sent.
+pub(crate) fn response(status: u16, headers: &[(&str, &str)], body: &str) -> String {
+    let mut s = format!("HTTP/1.1 {} Test\r\n", status);
+    for (k, v) in headers {
+        s.push_str(&format!("{}: {}\r\n", k, v));
+    }
+    s.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    s
+}
+
+//Answers one request per response in order then stops listening.
+pub(crate) fn serve(responses: Vec<String>) -> TestServer {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/demo/xmlmc", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for resp in responses {
+            let (mut stream, _) = match listener.accept() {
+                Ok(s) => s,
+                Err(_) => return,
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    break;
+                }
+                let lower = line.to_ascii_lowercase();
+                if let Some(v) = lower.strip_prefix("content-length:") {
+                    length = v.trim().parse().unwrap_or(0);
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            let _ = reader.read_exact(&mut body);
+            request.push_str(&String::from_utf8_lossy(&body));
+            let _ = tx.send(request);
+            let _ = stream.write_all(resp.as_bytes());
+        }
+    });
+
+    TestServer { url, requests: rx }
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for reqwest::blocking::Client {}
+    #[cfg(feature = "async")]
+    impl Sealed for reqwest::Client {}
+}
+
+/// The http clients an XmlmcClient can be built on. This is implemented for the blocking reqwest client (Xmlmc)
+/// and, with the async feature, the async reqwest client (AsyncXmlmc). It cannot be implemented outside this crate.
+pub trait HttpClient: sealed::Sealed + Sized {
+    #[doc(hidden)]
+    fn build(config: &ClientConfig) -> Result<Self, reqwest::Error>;
+}
+
+/// How to connect to the server: proxy, TLS trust and client certificate settings. These are used by the client
+/// (see XmlmcBuilder::connect_options) and by the zoneinfo lookups in get_url_from_name_with, which make their own
+/// connections.
+///
+/// By default the proxy is found the way reqwest always finds it, from the HTTP_PROXY, HTTPS_PROXY, ALL_PROXY and NO_PROXY
+/// environment variables and the system proxy settings. Once you set a proxy, proxy_auth or no_proxy, or call
+/// env_proxy(false), only your settings and (unless turned off) those environment variables are used.
+/// ```ignore
+/// let opts = ConnectOptions::new()
+///     .proxy("http://proxy.corp.local:8080")
+///     .proxy_auth("svc_import", "password")
+///     .add_root_certificate_pem(&std::fs::read("corp-ca.pem")?);
+/// ```
+#[derive(Clone)]
+pub struct ConnectOptions {
+    connect_timeout: Option<Duration>,
+    proxy: Option<String>,
+    proxy_auth: Option<(String, Zeroizing<String>)>,
+    no_proxy: Option<String>,
+    env_proxy: bool,
+    root_certificates: Vec<Vec<u8>>,
+    built_in_roots: bool,
+    identity: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> ConnectOptions {
+        ConnectOptions {
+            connect_timeout: None,
+            proxy: None,
+            proxy_auth: None,
+            no_proxy: None,
+            env_proxy: true,
+            root_certificates: Vec::new(),
+            built_in_roots: true,
+            identity: None,
+        }
+    }
+}
+
+//The proxy password and the client identity's private key are only shown as being set.
+impl fmt::Debug for ConnectOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectOptions")
+            .field("connect_timeout", &self.connect_timeout)
+            .field("proxy", &self.proxy)
+            .field(
+                "proxy_auth",
+                &self
+                    .proxy_auth
+                    .as_ref()
+                    .map(|(user, _)| (user, "[redacted]")),
+            )
+            .field("no_proxy", &self.no_proxy)
+            .field("env_proxy", &self.env_proxy)
+            .field("root_certificates", &self.root_certificates.len())
+            .field("built_in_roots", &self.built_in_roots)
+            .field("identity", &self.identity.as_ref().map(|_| "[redacted]"))
+            .finish()
+    }
+}
+
+impl ConnectOptions {
+    /// You can use this to start with the default options.
+    pub fn new() -> ConnectOptions {
+        ConnectOptions::default()
+    }
+
+    /// You can use this to set how long we wait for the connection to the server to open.
+    pub fn connect_timeout(mut self, timeout: Duration) -> ConnectOptions {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// You can use this to send all requests through a proxy. This takes priority over HTTPS_PROXY.
+    pub fn proxy(mut self, url: &str) -> ConnectOptions {
+        self.proxy = Some(url.to_owned());
+        self
+    }
+
+    /// You can use this to log on to an authenticating proxy with basic auth. The user and password can also be given
+    /// in the proxy url.
+    pub fn proxy_auth(mut self, user: &str, password: &str) -> ConnectOptions {
+        self.proxy_auth = Some((user.to_owned(), Zeroizing::new(password.to_owned())));
+        self
+    }
+
+    /// You can use this to list the hosts that should not go through the proxy, in the same comma separated format as
+    /// NO_PROXY. This takes priority over NO_PROXY.
+    /// ```ignore
+    /// let opts = ConnectOptions::new().no_proxy("localhost,.corp.local,10.0.0.0/8");
+    /// ```
+    pub fn no_proxy(mut self, hosts: &str) -> ConnectOptions {
+        self.no_proxy = Some(hosts.to_owned());
+        self
+    }
+
+    /// You can use this to choose if the HTTP_PROXY, HTTPS_PROXY, ALL_PROXY and NO_PROXY environment variables are read.
+    /// This defaults to true. Turning it off also stops the system proxy settings being used.
+    pub fn env_proxy(mut self, b: bool) -> ConnectOptions {
+        self.env_proxy = b;
+        self
+    }
+
+    /// You can use this to trust more root certificates, for example the CA of a proxy that intercepts TLS.
+    /// pem can hold a single certificate or a bundle of them.
+    pub fn add_root_certificate_pem(mut self, pem: &[u8]) -> ConnectOptions {
+        self.root_certificates.push(pem.to_vec());
+        self
+    }
+
+    /// You can use this to stop trusting the built in root certificates so only the ones you added are used.
+    pub fn tls_built_in_root_certs(mut self, b: bool) -> ConnectOptions {
+        self.built_in_roots = b;
+        self
+    }
+
+    /// You can use this to present a client certificate. pem must hold the private key and the certificate chain.
+    pub fn identity_pem(mut self, pem: &[u8]) -> ConnectOptions {
+        self.identity = Some(Zeroizing::new(pem.to_vec()));
+        self
+    }
+
+    //Returns true if the proxy has been set up here, so reqwest should not look for one itself.
+    fn has_proxy_settings(&self) -> bool {
+        self.proxy.is_some()
+            || self.proxy_auth.is_some()
+            || self.no_proxy.is_some()
+            || !self.env_proxy
+    }
+
+    //The proxies to use after looking at our own settings and then the environment, most specific first.
+    fn proxy_settings(&self) -> Result<Vec<reqwest::Proxy>, reqwest::Error> {
+        let mut proxies = Vec::new();
+        if let Some(url) = &self.proxy {
+            proxies.push(reqwest::Proxy::all(url)?);
+        } else if self.env_proxy {
+            if let Some(url) = env_var(&["HTTP_PROXY", "http_proxy"]) {
+                proxies.push(reqwest::Proxy::http(url)?);
+            }
+            if let Some(url) = env_var(&["HTTPS_PROXY", "https_proxy"]) {
+                proxies.push(reqwest::Proxy::https(url)?);
+            }
+            if let Some(url) = env_var(&["ALL_PROXY", "all_proxy"]) {
+                proxies.push(reqwest::Proxy::all(url)?);
+            }
+        }
+        let no_proxy = match &self.no_proxy {
+            Some(hosts) => Some(hosts.clone()),
+            None if self.env_proxy => env_var(&["NO_PROXY", "no_proxy"]),
+            None => None,
+        };
+        Ok(proxies
+            .into_iter()
+            .map(|mut proxy| {
+                if let Some((user, password)) = &self.proxy_auth {
+                    proxy = proxy.basic_auth(user, password);
+                }
+                proxy.no_proxy(no_proxy.as_deref().and_then(reqwest::NoProxy::from_string))
+            })
+            .collect())
+    }
+
+    fn root_certificates(&self) -> Result<Vec<reqwest::Certificate>, reqwest::Error> {
+        let mut certs = Vec::new();
+        for pem in &self.root_certificates {
+            let bundle = reqwest::Certificate::from_pem_bundle(pem)?;
+            //A bundle with nothing in it is not an error to reqwest but it is almost certainly the wrong file,
+            //so we parse it as a single certificate to get the error.
+            if bundle.is_empty() {
+                certs.push(reqwest::Certificate::from_pem(pem)?);
+            }
+            certs.extend(bundle);
+        }
+        Ok(certs)
+    }
+
+    fn identity(&self) -> Result<Option<reqwest::Identity>, reqwest::Error> {
+        self.identity
+            .as_deref()
+            .map(|pem| reqwest::Identity::from_pem(pem))
+            .transpose()
+    }
+}
+
+//The first environment variable in names that is set and not empty.
+fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|n| std::env::var(n).ok())
+        .find(|v| !v.trim().is_empty())
+}
+
+//The settings that have to be given to reqwest when the client is built rather than on each request.
+#[doc(hidden)]
+#[derive(Default)]
+pub struct ClientConfig {
+    pub(crate) connect: ConnectOptions,
+    pub(crate) default_headers: http::header::HeaderMap,
+    pub(crate) pool_idle_timeout: Option<Duration>,
+    pub(crate) pool_max_idle_per_host: Option<usize>,
+}
+
+impl From<&ConnectOptions> for ClientConfig {
+    fn from(connect: &ConnectOptions) -> ClientConfig {
+        ClientConfig {
+            connect: connect.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+//Both reqwest builders have the same methods but no shared trait so we apply the config with a macro.
+macro_rules! configure {
+    ($builder:expr, $config:expr) => {{
+        let config: &ClientConfig = $config;
+        let connect = &config.connect;
+        let mut builder = $builder
+            .default_headers(config.default_headers.clone())
+            .tls_built_in_root_certs(connect.built_in_roots);
+        //Unless the proxy has been set up in the ConnectOptions we leave reqwest to find it.
+        if connect.has_proxy_settings() {
+            builder = builder.no_proxy();
+            for proxy in connect.proxy_settings()? {
+                builder = builder.proxy(proxy);
+            }
+        }
+        if let Some(t) = connect.connect_timeout {
+            builder = builder.connect_timeout(t);
+        }
+        for cert in connect.root_certificates()? {
+            builder = builder.add_root_certificate(cert);
+        }
+        if let Some(identity) = connect.identity()? {
+            builder = builder.identity(identity);
+        }
+        if let Some(t) = config.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(t);
+        }
+        if let Some(n) = config.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(n);
+        }
+        builder.build()
+    }};
+}
+
+impl HttpClient for reqwest::blocking::Client {
+    fn build(config: &ClientConfig) -> Result<Self, reqwest::Error> {
+        configure!(reqwest::blocking::Client::builder(), config)
+    }
+}
+
+#[cfg(feature = "async")]
+impl HttpClient for reqwest::Client {
+    fn build(config: &ClientConfig) -> Result<Self, reqwest::Error> {
+        configure!(reqwest::Client::builder(), config)
+    }
+}
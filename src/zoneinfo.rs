@@ -0,0 +1,456 @@
+use crate::{ConnectOptions, HttpClient};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The zoneinfo for an instance, which says where the instance is hosted.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ZoneInfo {
+    /// The name of the instance this is for. This is not part of the zoneinfo response, we fill it in.
+    #[serde(default)]
+    pub instance: String,
+    #[serde(rename = "clusterFqn", default)]
+    pub cluster_fqn: String,
+    #[serde(rename = "releaseStream", default)]
+    pub release_stream: String,
+    pub endpoint: String,
+    #[serde(rename = "apiEndpoint")]
+    pub api_endpoint: Option<String>,
+    pub message: String,
+}
+
+impl ZoneInfo {
+    /// The xmlmc url to create a client with. Older zoneinfo responses do not have an apiEndpoint so we build it from the endpoint.
+    pub fn xmlmc_url(&self) -> String {
+        match &self.api_endpoint {
+            Some(url) => url.clone(),
+            None => format!("{}xmlmc/", self.endpoint),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Root {
+    zoneinfo: ZoneInfo,
+}
+
+//What we keep on disk for each instance.
+#[derive(Deserialize, Serialize)]
+struct CacheEntry {
+    //Seconds since the unix epoch when the zoneinfo was fetched.
+    fetched: u64,
+    zoneinfo: ZoneInfo,
+}
+
+/// Why the zoneinfo for an instance could not be found.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ResolveError {
+    /// The instance name is empty or has characters that cannot be in an instance name.
+    InvalidInstance { instance: String },
+    /// The http client used for the lookup could not be created.
+    Build { source: reqwest::Error },
+    /// The request to a mirror could not be sent or its body could not be read.
+    Transport { url: String, source: reqwest::Error },
+    /// A mirror responded with a http status code other than 200.
+    Status { url: String, status: u16 },
+    /// A mirror responded with something that is not zoneinfo.
+    Parse { url: String, message: String },
+    /// A mirror responded with zoneinfo but the message was not "Success".
+    Unavailable { url: String, message: String },
+    /// Every mirror failed. errors holds what went wrong with each one in the order they were tried, and source returns
+    /// the last of them.
+    AllMirrorsFailed {
+        instance: String,
+        errors: Vec<ResolveError>,
+    },
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::InvalidInstance { instance } => {
+                write!(f, "{:?} is not a valid instance name", instance)
+            }
+            ResolveError::Build { .. } => {
+                write!(f, "could not build the http client")
+            }
+            ResolveError::Transport { url, .. } => {
+                write!(f, "{} request failed", url)
+            }
+            ResolveError::Status { url, status } => {
+                write!(f, "{} returned http status {}", url, status)
+            }
+            ResolveError::Parse { url, message } => {
+                write!(f, "{} did not return zoneinfo: {}", url, message)
+            }
+            ResolveError::Unavailable { url, message } => {
+                write!(f, "{} returned zoneinfo with message {:?}", url, message)
+            }
+            ResolveError::AllMirrorsFailed { instance, errors } => {
+                write!(
+                    f,
+                    "could not get zoneinfo for {} from any of the {} mirrors",
+                    instance,
+                    errors.len()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResolveError::Build { source } | ResolveError::Transport { source, .. } => Some(source),
+            ResolveError::AllMirrorsFailed { errors, .. } => errors
+                .last()
+                .map(|e| e as &(dyn std::error::Error + 'static)),
+            _ => None,
+        }
+    }
+}
+
+/// Looks up the zoneinfo for an instance. Each mirror is tried in order until one of them answers with zoneinfo.
+///
+/// With cache_dir set the zoneinfo is saved to disk and reused until it is older than the cache ttl. If the zoneinfo
+/// servers cannot be reached an expired entry is used rather than failing.
+/// ```ignore
+/// let zone = ZoneInfoResolver::new().resolve("demo")?;
+/// println!("{} is on {}", zone.instance, zone.cluster_fqn);
+/// let c = Xmlmc::new(&zone.xmlmc_url())?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct ZoneInfoResolver {
+    mirrors: Vec<String>,
+    pub(crate) timeout: Duration,
+    pub(crate) options: ConnectOptions,
+    pub(crate) cache_dir: Option<PathBuf>,
+    cache_ttl: Duration,
+}
+
+impl Default for ZoneInfoResolver {
+    fn default() -> ZoneInfoResolver {
+        ZoneInfoResolver::new()
+    }
+}
+
+impl ZoneInfoResolver {
+    /// You can use this to create a resolver that uses the hornbill zoneinfo servers, files.hornbill.com then files.hornbill.co.
+    pub fn new() -> ZoneInfoResolver {
+        ZoneInfoResolver {
+            mirrors: vec![
+                "https://files.hornbill.com".to_owned(),
+                "https://files.hornbill.co".to_owned(),
+            ],
+            timeout: Duration::from_secs(10),
+            options: ConnectOptions::default(),
+            cache_dir: None,
+            cache_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    /// You can use this to replace the list of zoneinfo servers. The zoneinfo is read from {mirror}/instances/{instance}/zoneinfo.
+    /// ```ignore
+    /// let resolver = ZoneInfoResolver::new().mirrors(&["https://files.mirror.local"]);
+    /// ```
+    pub fn mirrors(mut self, mirrors: &[&str]) -> ZoneInfoResolver {
+        self.mirrors = mirrors
+            .iter()
+            .map(|m| m.trim_end_matches('/').to_owned())
+            .collect();
+        self
+    }
+
+    /// You can use this to set how long each request can take. This defaults to 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> ZoneInfoResolver {
+        self.timeout = timeout;
+        self
+    }
+
+    /// You can use this to set the proxy and TLS options used to reach the zoneinfo servers.
+    pub fn connect_options(mut self, options: ConnectOptions) -> ZoneInfoResolver {
+        self.options = options;
+        self
+    }
+
+    /// You can use this to keep the zoneinfo on disk in dir, one file per instance. The directory is created if it does not exist.
+    /// Problems reading or writing the cache are ignored and the zoneinfo servers are used instead.
+    /// ```ignore
+    /// let resolver = ZoneInfoResolver::new().cache_dir(ZoneInfoResolver::default_cache_dir().unwrap());
+    /// ```
+    pub fn cache_dir<P: AsRef<Path>>(mut self, dir: P) -> ZoneInfoResolver {
+        self.cache_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// You can use this to set how long cached zoneinfo is used before it is fetched again. This defaults to 24 hours.
+    pub fn cache_ttl(mut self, ttl: Duration) -> ZoneInfoResolver {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// The directory we suggest for the cache: hornbill_apilib inside XDG_CACHE_HOME, ~/.cache or %LOCALAPPDATA%.
+    pub fn default_cache_dir() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+        Some(base.join("hornbill_apilib"))
+    }
+
+    /// You can use this to get the zoneinfo for an instance.
+    /// ```ignore
+    /// let zone = ZoneInfoResolver::new().resolve("demo")?;
+    /// ```
+    pub fn resolve(&self, instance: &str) -> Result<ZoneInfo, ResolveError> {
+        check_instance(instance)?;
+        let cached = self.read_cache(instance);
+        if let Some((zone, true)) = &cached {
+            return Ok(zone.clone());
+        }
+        match self.fetch(instance) {
+            Ok(zone) => {
+                self.write_cache(&zone);
+                Ok(zone)
+            }
+            Err(e) => cached.map(|(zone, _)| zone).ok_or(e),
+        }
+    }
+
+    /// You can use this to get the zoneinfo from the zoneinfo servers even if it is in the cache, and save it to the cache.
+    /// Unlike resolve this fails if the servers cannot be reached.
+    /// ```ignore
+    /// let zone = resolver.refresh("demo")?;
+    /// ```
+    pub fn refresh(&self, instance: &str) -> Result<ZoneInfo, ResolveError> {
+        check_instance(instance)?;
+        let zone = self.fetch(instance)?;
+        self.write_cache(&zone);
+        Ok(zone)
+    }
+
+    fn fetch(&self, instance: &str) -> Result<ZoneInfo, ResolveError> {
+        let client = reqwest::blocking::Client::build(&(&self.options).into())
+            .map_err(|e| ResolveError::Build { source: e })?;
+
+        let mut errors = Vec::new();
+        for url in self.urls(instance) {
+            let result = client
+                .get(&url)
+                .timeout(self.timeout)
+                .header(http::header::USER_AGENT, "reqwest-http/1.1")
+                .send()
+                .and_then(|r| {
+                    let status = r.status().as_u16();
+                    r.text().map(|body| (status, body))
+                });
+            match result {
+                Ok((status, body)) => match parse(instance, &url, status, &body) {
+                    Ok(zone) => return Ok(zone),
+                    Err(e) => errors.push(e),
+                },
+                Err(e) => errors.push(ResolveError::Transport { url, source: e }),
+            }
+        }
+        Err(ResolveError::AllMirrorsFailed {
+            instance: instance.to_owned(),
+            errors,
+        })
+    }
+
+    //The cached zoneinfo for an instance and whether it is still inside the ttl.
+    pub(crate) fn read_cache(&self, instance: &str) -> Option<(ZoneInfo, bool)> {
+        let path = self.cache_dir.as_ref()?.join(format!("{}.json", instance));
+        let entry: CacheEntry = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+        let age = now().saturating_sub(entry.fetched);
+        Some((entry.zoneinfo, age < self.cache_ttl.as_secs()))
+    }
+
+    pub(crate) fn write_cache(&self, zone: &ZoneInfo) {
+        let dir = match &self.cache_dir {
+            Some(d) => d,
+            None => return,
+        };
+        let entry = CacheEntry {
+            fetched: now(),
+            zoneinfo: zone.clone(),
+        };
+        let json = match serde_json::to_vec(&entry) {
+            Ok(j) => j,
+            Err(_) => return,
+        };
+        //Write to a temporary file and rename it so other processes never read half a file. The counter keeps threads
+        //in the same process that write the same instance at once from sharing a temporary file.
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+        let path = dir.join(format!("{}.json", zone.instance));
+        let tmp = dir.join(format!(
+            "{}.json.{}.{}",
+            zone.instance,
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(&tmp, json))
+            .and_then(|_| std::fs::rename(&tmp, &path));
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+    }
+
+    pub(crate) fn urls(&self, instance: &str) -> Vec<String> {
+        self.mirrors
+            .iter()
+            .map(|m| format!("{}/instances/{}/zoneinfo", m, instance))
+            .collect()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+//Instance names end up in the url path and cache file name so we only allow the characters hornbill uses for them.
+pub(crate) fn check_instance(instance: &str) -> Result<(), ResolveError> {
+    if instance.is_empty()
+        || !instance
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(ResolveError::InvalidInstance {
+            instance: instance.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+//Reads the zoneinfo out of a response from one mirror.
+pub(crate) fn parse(
+    instance: &str,
+    url: &str,
+    status: u16,
+    body: &str,
+) -> Result<ZoneInfo, ResolveError> {
+    if status != 200 {
+        return Err(ResolveError::Status {
+            url: url.to_owned(),
+            status,
+        });
+    }
+    let mut zone = match serde_json::from_str::<Root>(body) {
+        Ok(root) => root.zoneinfo,
+        Err(e) => {
+            return Err(ResolveError::Parse {
+                url: url.to_owned(),
+                message: e.to_string(),
+            })
+        }
+    };
+    //Check we got a successful repsonse from server.
+    if zone.message != "Success" {
+        return Err(ResolveError::Unavailable {
+            url: url.to_owned(),
+            message: zone.message,
+        });
+    }
+    zone.instance = instance.to_owned();
+    Ok(zone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use std::error::Error;
+
+    #[test]
+    fn test_resolve() {
+        let zoneinfo = r#"{"zoneinfo":{"clusterFqn":"mdh-p01","releaseStream":"stable","endpoint":"https://mdh-p01-api.hornbill.com/demo/","message":"Success"}}"#;
+        let down = test_server::serve(vec![test_server::response(503, &[], "down")]);
+        let up = test_server::serve(vec![test_server::response(200, &[], zoneinfo)]);
+        let base = |url: &str| url.trim_end_matches("/demo/xmlmc").to_owned();
+
+        let resolver = ZoneInfoResolver::new().mirrors(&[&base(&down.url), &base(&up.url)]);
+        let zone = resolver.resolve("demo").unwrap();
+        assert_eq!(zone.instance, "demo");
+        assert_eq!(zone.cluster_fqn, "mdh-p01");
+        assert_eq!(zone.release_stream, "stable");
+        assert_eq!(
+            zone.xmlmc_url(),
+            "https://mdh-p01-api.hornbill.com/demo/xmlmc/"
+        );
+        //Each mirror is only asked once.
+        assert!(up
+            .requests
+            .recv()
+            .unwrap()
+            .starts_with("GET /instances/demo/zoneinfo"));
+        assert_eq!(up.requests.iter().count(), 0);
+        assert_eq!(down.requests.iter().count(), 1);
+
+        let err = resolver.resolve("../demo").unwrap_err();
+        assert!(matches!(err, ResolveError::InvalidInstance { .. }));
+
+        //Nothing is listening on the mirrors any more.
+        let err = resolver.resolve("demo").unwrap_err();
+        assert!(
+            matches!(err, ResolveError::AllMirrorsFailed { ref errors, .. } if errors.len() == 2)
+        );
+        assert_eq!(
+            err.to_string(),
+            "could not get zoneinfo for demo from any of the 2 mirrors"
+        );
+        //source is the last mirror's error, which wraps the reqwest error.
+        let last = err
+            .source()
+            .unwrap()
+            .downcast_ref::<ResolveError>()
+            .unwrap();
+        assert!(
+            matches!(last, ResolveError::Transport { url, .. } if url.starts_with(&base(&up.url)))
+        );
+        assert!(last.source().unwrap().is::<reqwest::Error>());
+    }
+
+    #[test]
+    fn test_cache() {
+        let zoneinfo = r#"{"zoneinfo":{"clusterFqn":"mdh-p01","releaseStream":"stable","endpoint":"https://mdh-p01-api.hornbill.com/demo/","apiEndpoint":"https://mdh-p01-api.hornbill.com/demo/xmlmc/","message":"Success"}}"#;
+        let server = test_server::serve(vec![test_server::response(200, &[], zoneinfo)]);
+        let mirror = server.url.trim_end_matches("/demo/xmlmc").to_owned();
+        let dir = tempfile::tempdir().unwrap();
+
+        let resolver = ZoneInfoResolver::new()
+            .mirrors(&[&mirror])
+            .cache_dir(dir.path().join("zones"));
+        let zone = resolver.resolve("demo").unwrap();
+        assert!(dir.path().join("zones/demo.json").exists());
+        assert_eq!(server.requests.iter().count(), 1);
+
+        //The server has gone away so these can only come from the cache.
+        assert_eq!(resolver.resolve("demo").unwrap(), zone);
+        let expired = resolver.clone().cache_ttl(Duration::ZERO);
+        assert_eq!(
+            expired.read_cache("demo").map(|(_, fresh)| fresh),
+            Some(false)
+        );
+        assert_eq!(expired.resolve("demo").unwrap(), zone);
+        assert!(resolver.refresh("demo").is_err());
+
+        //Threads writing the same instance at once each use their own temporary file.
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| resolver.write_cache(&zone));
+            }
+        });
+        assert_eq!(resolver.read_cache("demo").unwrap().0, zone);
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("zones")).unwrap().count(),
+            1
+        );
+    }
+}